@@ -1,184 +1,347 @@
-// ignore unused warning
-#![allow(dead_code)]
-
-use std::ffi::CString;
-use std::fs::File;
-use std::io::{self, BufRead};
-use std::os::raw::c_char;
-
-extern "C" {
-    fn cgroup_new_cgroup(name: *const c_char) -> *mut libc::c_void;
-    fn cgroup_add_controller(
-        cgroup: *mut libc::c_void,
-        controller: *const c_char,
-    ) -> *mut libc::c_void;
-    fn cgroup_add_value_string(
-        controller: *mut libc::c_void,
-        name: *const c_char,
-        value: *const c_char,
-    ) -> i32;
-    fn cgroup_get_value_string(
-        controller: *mut libc::c_void,
-        name: *const c_char,
-        value: *mut *mut c_char,
-    ) -> i32;
-    fn cgroup_strerror(err: i32) -> *const c_char;
+use anyhow::anyhow;
+use nix::errno::Errno;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::fs::{self, File};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::JudgeCoreError;
+
+pub static CGROUP_MOUNT_PATH: &str = "/sys/fs/cgroup";
+/// Name of the cgroup (under the judger's own cgroup) where every run is created
+pub static CGROUP_RUNS_DIRNAME: &str = "judger";
+/// Name of the leaf cgroup which the judger moves itself into,
+/// cgroup v2 forbids enabling controllers for a cgroup which has processes in it.
+pub static CGROUP_SUPERVISOR_DIRNAME: &str = "supervisor";
+
+const CGROUP_CONTROLLERS: [&str; 3] = ["cpu", "memory", "pids"];
+/// The unit of `cpu.max` period is microsecond
+const CPU_MAX_PERIOD: u64 = 100_000;
+/// How long to wait for killed processes to leave the cgroup before removing it
+const CGROUP_DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+static CGROUP_RUNS_ROOT: OnceLock<Option<PathBuf>> = OnceLock::new();
+static CGROUP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Get the cgroup under which every run is placed.
+///
+/// Returns `None` when cgroup v2 is not mounted or the needed controllers are not delegated,
+/// in which case the sandbox should fall back to rlimits.
+/// The preparation only happens once in a process.
+pub fn get_cgroup_runs_root() -> Option<&'static PathBuf> {
+    CGROUP_RUNS_ROOT
+        .get_or_init(|| match prepare_cgroup_runs_root() {
+            Ok(runs_root) => {
+                log::info!("Using cgroup v2 at {:?}", runs_root);
+                Some(runs_root)
+            }
+            Err(e) => {
+                log::warn!("cgroup v2 is not available, fallback to rlimit: {:?}", e);
+                None
+            }
+        })
+        .as_ref()
 }
 
-pub enum CGroupError {
-    ECGROUPNOTCOMPILED = 50000,
-    ECGROUPNOTMOUNTED,
-    ECGROUPNOTEXIST,
-    ECGROUPNOTCREATED,
-    ECGROUPSUBSYSNOTMOUNTED,
-    ECGROUPNOTOWNER,
-    /** Controllers bound to different mount points */
-    ECGROUPMULTIMOUNTED,
-    /* This is the stock error. Default error. @todo really? */
-    ECGROUPNOTALLOWED,
-    ECGMAXVALUESEXCEEDED,
-    ECGCONTROLLEREXISTS,
-    ECGVALUEEXISTS,
-    ECGINVAL,
-    ECGCONTROLLERCREATEFAILED,
-    ECGFAIL,
-    ECGROUPNOTINITIALIZED,
-    ECGROUPVALUENOTEXIST,
-    /**
-     * Represents error coming from other libraries like glibc. @c libcgroup
-     * users need to check cgroup_get_last_errno() upon encountering this
-     * error.
-     */
-    ECGOTHER,
-    ECGROUPNOTEQUAL,
-    ECGCONTROLLERNOTEQUAL,
-    /** Failed to parse rules configuration file. */
-    ECGROUPPARSEFAIL,
-    /** Rules list does not exist. */
-    ECGROUPNORULES,
-    ECGMOUNTFAIL,
-    /**
-     * Not an real error, it just indicates that iterator has come to end
-     * of sequence and no more items are left.
-     */
-    ECGEOF = 50023,
-    /** Failed to parse config file (cgconfig.conf). */
-    ECGCONFIGPARSEFAIL,
-    ECGNAMESPACEPATHS,
-    ECGNAMESPACECONTROLLER,
-    ECGMOUNTNAMESPACE,
-    ECGROUPUNSUPP,
-    ECGCANTSETVALUE,
-    /** Removing of a group failed because it was not empty. */
-    ECGNONEMPTY,
+/// A cgroup v2 node owned by a single run, removed on drop.
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
 }
 
-struct CGroup {
-    cgroup: *mut libc::c_void,
+#[derive(Debug, Clone, Copy)]
+pub struct CgroupCpuUsage {
+    pub user_time: Duration,
+    pub system_time: Duration,
 }
 
-impl CGroup {
-    fn new(name: &str) -> Self {
-        let cgroup_name = CString::new(name).expect("CString::new failed");
-        unsafe {
-            let cgroup = cgroup_new_cgroup(cgroup_name.as_ptr());
-            if cgroup.is_null() {
-                panic!("Failed to create cgroup");
-            }
-            CGroup { cgroup }
+impl Cgroup {
+    /// Create a new cgroup under the runs root with an unique name.
+    pub fn new() -> Result<Self, JudgeCoreError> {
+        let runs_root = get_cgroup_runs_root()
+            .ok_or_else(|| JudgeCoreError::AnyhowError(anyhow!("cgroup v2 is not available")))?;
+        let name = format!(
+            "run-{}-{}",
+            std::process::id(),
+            CGROUP_COUNTER.fetch_add(1, Ordering::SeqCst)
+        );
+        let path = runs_root.join(name);
+        fs::create_dir(&path)?;
+        log::debug!("Created cgroup {:?}", path);
+        Ok(Self { path })
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writing `0` to this file moves the writer into the cgroup.
+    pub fn get_procs_path(&self) -> PathBuf {
+        self.path.join("cgroup.procs")
+    }
+
+    /// Swap is disabled as well when it is supported,
+    /// otherwise the memory could escape from `memory.max`.
+    pub fn set_memory_max(&self, bytes: u64) -> Result<(), JudgeCoreError> {
+        write_cgroup_file(&self.path, "memory.max", &bytes.to_string())?;
+        if self.path.join("memory.swap.max").exists() {
+            write_cgroup_file(&self.path, "memory.swap.max", "0")?;
         }
+        Ok(())
+    }
+
+    pub fn set_pids_max(&self, max: u64) -> Result<(), JudgeCoreError> {
+        write_cgroup_file(&self.path, "pids.max", &max.to_string())
     }
 
-    fn add_controller(&self, controller: &str) -> *mut libc::c_void {
-        let controller = CString::new(controller).expect("CString::new failed");
-        unsafe {
-            let ret = cgroup_add_controller(self.cgroup, controller.as_ptr());
-            if ret.is_null() {
-                eprintln!("Failed to add controller to cgroup");
+    /// Limit the bandwidth to `cpus` cores.
+    pub fn set_cpu_max(&self, cpus: u64) -> Result<(), JudgeCoreError> {
+        write_cgroup_file(
+            &self.path,
+            "cpu.max",
+            &format!("{} {}", cpus * CPU_MAX_PERIOD, CPU_MAX_PERIOD),
+        )
+    }
+
+    /// `memory.peak` is only provided since Linux 5.19.
+    pub fn get_memory_peak(&self) -> Result<u64, JudgeCoreError> {
+        let content = fs::read_to_string(self.path.join("memory.peak"))?;
+        parse_u64(content.trim())
+    }
+
+    /// Memory charged to this cgroup at the moment, page cache included.
+    pub fn get_memory_current(&self) -> Result<u64, JudgeCoreError> {
+        let content = fs::read_to_string(self.path.join("memory.current"))?;
+        parse_u64(content.trim())
+    }
+
+    /// Page cache charged to this cgroup at the moment, e.g. of the files written by the run,
+    /// which stays charged after the processes exit.
+    pub fn get_memory_file(&self) -> Result<u64, JudgeCoreError> {
+        read_flat_keyed(&self.path.join("memory.stat"), "file")
+    }
+
+    pub fn get_cpu_usage(&self) -> Result<CgroupCpuUsage, JudgeCoreError> {
+        let user_usec = read_flat_keyed(&self.path.join("cpu.stat"), "user_usec")?;
+        let system_usec = read_flat_keyed(&self.path.join("cpu.stat"), "system_usec")?;
+        Ok(CgroupCpuUsage {
+            user_time: Duration::from_micros(user_usec),
+            system_time: Duration::from_micros(system_usec),
+        })
+    }
+
+    /// How many processes in this cgroup were killed for reaching `memory.max`.
+    pub fn get_oom_kill_count(&self) -> Result<u64, JudgeCoreError> {
+        read_flat_keyed(&self.path.join("memory.events"), "oom_kill")
+    }
+
+    /// Whether any process is still in this cgroup, zombies excluded.
+    pub fn is_populated(&self) -> Result<bool, JudgeCoreError> {
+        Ok(read_flat_keyed(&self.path.join("cgroup.events"), "populated")? > 0)
+    }
+
+    /// Kill every process in this cgroup with `SIGKILL`.
+    ///
+    /// `cgroup.kill` does it atomically but is only provided since Linux 5.14,
+    /// otherwise the listed processes are killed until the cgroup is empty,
+    /// which also catches the ones forked in the meantime.
+    pub fn kill(&self) -> Result<(), JudgeCoreError> {
+        if self.path.join("cgroup.kill").exists() {
+            return write_cgroup_file(&self.path, "cgroup.kill", "1");
+        }
+        let deadline = Instant::now() + CGROUP_DRAIN_TIMEOUT;
+        while self.is_populated()? && Instant::now() < deadline {
+            let procs = fs::read_to_string(self.get_procs_path())?;
+            for pid in procs.lines() {
+                let pid = Pid::from_raw(parse_u64(pid)? as i32);
+                match kill(pid, Signal::SIGKILL) {
+                    Ok(()) | Err(Errno::ESRCH) => {}
+                    Err(e) => return Err(JudgeCoreError::NixErrno(e)),
+                }
             }
-            ret
+            thread::sleep(Duration::from_millis(1));
         }
+        Ok(())
     }
 
-    fn add_value_string(&self, controller: *mut libc::c_void, name: &str, value: &str) {
-        let name = CString::new(name).expect("CString::new failed");
-        let value = CString::new(value).expect("CString::new failed");
-        unsafe {
-            let ret = cgroup_add_value_string(controller, name.as_ptr(), value.as_ptr());
-            if ret != 0 {
-                eprintln!(
-                    "Failed to add value to cgroup: {}",
-                    cgroup_strerror_safe(ret)
-                );
+    /// Block until the cgroup has no process or `timeout` is reached.
+    fn drain(&self, timeout: Duration) -> Result<(), JudgeCoreError> {
+        let deadline = Instant::now() + timeout;
+        while self.is_populated()? {
+            if Instant::now() >= deadline {
+                return Err(JudgeCoreError::AnyhowError(anyhow!(
+                    "cgroup {:?} is still populated",
+                    self.path
+                )));
             }
+            thread::sleep(Duration::from_millis(1));
         }
+        Ok(())
     }
+}
 
-    fn cgroup_get_value_string(&self, controller: *mut libc::c_void, name: &str) -> String {
-        let name = CString::new(name).expect("CString::new failed");
-        let mut value: *mut c_char = std::ptr::null_mut();
-        unsafe {
-            let ret = cgroup_get_value_string(controller, name.as_ptr(), &mut value);
-            if ret != 0 {
-                eprintln!(
-                    "Failed to get value from cgroup: {}",
-                    cgroup_strerror_safe(ret)
-                );
-            }
-            let value = std::ffi::CStr::from_ptr(value).to_str().unwrap();
-            value.to_string()
+impl Drop for Cgroup {
+    /// A populated cgroup can not be removed, so the processes left are killed first.
+    fn drop(&mut self) {
+        if let Err(e) = self.kill().and_then(|_| self.drain(CGROUP_DRAIN_TIMEOUT)) {
+            log::warn!("Failed to empty cgroup {:?}: {:?}", self.path, e);
+        }
+        if let Err(e) = fs::remove_dir(&self.path) {
+            log::warn!("Failed to remove cgroup {:?}: {:?}", self.path, e);
         }
     }
 }
 
-fn cgroup_is_v2() -> bool {
-    let file = match File::open("/proc/mounts") {
-        Ok(file) => file,
-        Err(_) => {
-            eprintln!("Error opening /proc/mounts");
-            return false;
+fn prepare_cgroup_runs_root() -> Result<PathBuf, JudgeCoreError> {
+    if !cgroup_is_v2()? {
+        return Err(JudgeCoreError::AnyhowError(anyhow!(
+            "cgroup2 is not mounted at {}",
+            CGROUP_MOUNT_PATH
+        )));
+    }
+
+    let self_cgroup = get_self_cgroup_path()?;
+    log::debug!("Judger is running in cgroup {:?}", self_cgroup);
+    let controllers = fs::read_to_string(self_cgroup.join("cgroup.controllers"))?;
+    for controller in CGROUP_CONTROLLERS.iter() {
+        if !controllers.split_whitespace().any(|c| c == *controller) {
+            return Err(JudgeCoreError::AnyhowError(anyhow!(
+                "cgroup controller {} is not delegated to {:?}",
+                controller,
+                self_cgroup
+            )));
         }
-    };
+    }
 
-    let reader = io::BufReader::new(file);
-    for line in reader.lines().flatten() {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() >= 3 && parts[1] == "/sys/fs/cgroup" && parts[2] == "cgroup2" {
-            return true;
+    match enable_controllers(&self_cgroup) {
+        Ok(()) => {}
+        Err(JudgeCoreError::IOError(e)) if e.raw_os_error() == Some(Errno::EBUSY as i32) => {
+            log::info!("Moving judger processes into a supervisor cgroup");
+            let supervisor = self_cgroup.join(CGROUP_SUPERVISOR_DIRNAME);
+            if !supervisor.exists() {
+                fs::create_dir(&supervisor)?;
+            }
+            let procs = fs::read_to_string(self_cgroup.join("cgroup.procs"))?;
+            for pid in procs.lines() {
+                // The process may have exited in the meantime
+                if let Err(e) = write_cgroup_file(&supervisor, "cgroup.procs", pid) {
+                    log::debug!("Failed to move pid={} to supervisor: {:?}", pid, e);
+                }
+            }
+            enable_controllers(&self_cgroup)?;
         }
+        Err(e) => return Err(e),
+    }
+
+    let runs_root = self_cgroup.join(CGROUP_RUNS_DIRNAME);
+    if !runs_root.exists() {
+        fs::create_dir(&runs_root)?;
     }
+    enable_controllers(&runs_root)?;
+    Ok(runs_root)
+}
 
-    false
+fn enable_controllers(path: &Path) -> Result<(), JudgeCoreError> {
+    let content = CGROUP_CONTROLLERS
+        .iter()
+        .map(|controller| format!("+{}", controller))
+        .collect::<Vec<_>>()
+        .join(" ");
+    write_cgroup_file(path, "cgroup.subtree_control", &content)
 }
 
-pub fn cgroup_strerror_safe(err: i32) -> String {
-    unsafe {
-        let errstr = cgroup_strerror(err);
-        let errstr = std::ffi::CStr::from_ptr(errstr).to_str().unwrap();
-        errstr.to_string()
+fn cgroup_is_v2() -> Result<bool, JudgeCoreError> {
+    let reader = io::BufReader::new(File::open("/proc/mounts")?);
+    for line in reader.lines() {
+        let line = line?;
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() >= 3 && parts[1] == CGROUP_MOUNT_PATH && parts[2] == "cgroup2" {
+            return Ok(true);
+        }
     }
+    Ok(false)
 }
 
-#[test]
-fn test_cgroup() {
-    let cgroup = CGroup::new("my_cgroup");
+/// In cgroup v2 `/proc/self/cgroup` only contains one line like `0::/some/path`.
+fn get_self_cgroup_path() -> Result<PathBuf, JudgeCoreError> {
+    let content = fs::read_to_string("/proc/self/cgroup")?;
+    for line in content.lines() {
+        if let Some(relative_path) = line.strip_prefix("0::") {
+            return Ok(PathBuf::from(CGROUP_MOUNT_PATH).join(relative_path.trim_start_matches('/')));
+        }
+    }
+    Err(JudgeCoreError::AnyhowError(anyhow!(
+        "Failed to find cgroup v2 path in /proc/self/cgroup"
+    )))
+}
+
+fn write_cgroup_file(path: &Path, name: &str, content: &str) -> Result<(), JudgeCoreError> {
+    let mut file = fs::OpenOptions::new().write(true).open(path.join(name))?;
+    file.write_all(content.as_bytes())?;
+    Ok(())
+}
 
-    if cgroup_is_v2() {
-        println!("cgroup v2 is enabled");
-    } else {
-        println!("cgroup v2 is not enabled");
+/// Read a value from flat keyed files like `cpu.stat` and `memory.events`.
+fn read_flat_keyed(path: &Path, key: &str) -> Result<u64, JudgeCoreError> {
+    let content = fs::read_to_string(path)?;
+    for line in content.lines() {
+        let mut parts = line.split_whitespace();
+        if parts.next() == Some(key) {
+            if let Some(value) = parts.next() {
+                return parse_u64(value);
+            }
+        }
     }
+    Err(JudgeCoreError::AnyhowError(anyhow!(
+        "Key {} not found in {:?}",
+        key,
+        path
+    )))
+}
 
-    let controller = cgroup.add_controller("cpuset");
-    cgroup.add_value_string(controller, "cpuset.cpus", "0-1");
-    let value = cgroup.cgroup_get_value_string(controller, "cpuset.cpus");
-    println!("{}", value);
+fn parse_u64(value: &str) -> Result<u64, JudgeCoreError> {
+    value
+        .parse::<u64>()
+        .map_err(|e| JudgeCoreError::AnyhowError(e.into()))
 }
 
 #[test]
-fn test_cgroup_strerror() {
-    println!(
-        "{}",
-        cgroup_strerror_safe(CGroupError::ECGROUPNOTCOMPILED as i32)
-    );
+fn test_read_flat_keyed() {
+    let path = std::env::temp_dir().join("judge-core-test-cpu.stat");
+    fs::write(&path, "usage_usec 1500\nuser_usec 1000\nsystem_usec 500\n").unwrap();
+    assert_eq!(read_flat_keyed(&path, "user_usec").unwrap(), 1000);
+    assert_eq!(read_flat_keyed(&path, "system_usec").unwrap(), 500);
+    assert!(read_flat_keyed(&path, "nr_throttled").is_err());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_get_cgroup_runs_root() {
+    let runs_root = match get_cgroup_runs_root() {
+        Some(runs_root) => runs_root,
+        None => {
+            println!("Skipped: cgroup v2 is not mounted or delegated to the test process");
+            return;
+        }
+    };
+    assert!(runs_root.ends_with(CGROUP_RUNS_DIRNAME));
+    let controllers = fs::read_to_string(runs_root.join("cgroup.subtree_control")).unwrap();
+    for controller in CGROUP_CONTROLLERS.iter() {
+        assert!(controllers.split_whitespace().any(|c| c == *controller));
+    }
+
+    let cgroup = Cgroup::new().unwrap();
+    let path = cgroup.get_path().to_path_buf();
+    assert!(path.starts_with(runs_root));
+    cgroup.set_memory_max(64 << 20).unwrap();
+    assert_eq!(cgroup.get_memory_current().unwrap(), 0);
+    assert_eq!(cgroup.get_oom_kill_count().unwrap(), 0);
+    assert!(!cgroup.is_populated().unwrap());
+    drop(cgroup);
+    assert!(!path.exists());
 }
@@ -1,10 +1,14 @@
 use crate::error::JudgeCoreError;
+use crate::sandbox::cancellation::CancellationToken;
 use crate::utils::get_pathbuf_str;
 use anyhow::anyhow;
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::Pid;
 use serde_derive::{Deserialize, Serialize};
+use std::os::unix::process::CommandExt;
 use std::path::PathBuf;
-use std::process::Stdio;
-use std::time::Duration;
+use std::process::{Child, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
 use std::{fmt, fs};
 use std::{process::Command, str::FromStr};
 use wait_timeout::ChildExt;
@@ -17,6 +21,8 @@ const CPP_COMPILE_COMMAND_TEMPLATE: &str = "g++ {src_path} -o {target_path} -O2
 const PYTHON_COMPILE_COMMAND_TEMPLATE: &str = "cp {src_path} {target_path}";
 
 const COMPILE_TIMEOUT: Duration = Duration::from_secs(15);
+/// How often a running compile checks its cancellation token
+const COMPILE_CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(20);
 
 #[derive(Clone)]
 struct CommandBuilder {
@@ -42,7 +48,7 @@ impl CommandBuilder {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Copy, Serialize, Deserialize)]
 pub enum Language {
     #[serde(rename = "rust")]
     Rust,
@@ -111,6 +117,7 @@ pub struct Compiler {
     language: Language,
     command_builder: CommandBuilder,
     compiler_args: Vec<String>,
+    cancellation_token: Option<CancellationToken>,
 }
 
 impl Compiler {
@@ -134,9 +141,16 @@ impl Compiler {
             language,
             command_builder,
             compiler_args,
+            cancellation_token: None,
         }
     }
 
+    /// Cancelling the token kills the running compile,
+    /// which then fails with `JudgeCoreError::Cancelled`.
+    pub fn set_cancellation_token(&mut self, cancellation_token: Option<CancellationToken>) {
+        self.cancellation_token = cancellation_token;
+    }
+
     pub fn compile(
         &self,
         src_path: &PathBuf,
@@ -167,6 +181,7 @@ impl Compiler {
             std::fs::remove_file(target_path)?;
         }
 
+        // In its own process group, so that the compiler forked by `sh` is killed along
         let mut child = Command::new("sh")
             .arg("-c")
             .arg(
@@ -176,9 +191,10 @@ impl Compiler {
             .args(self.compiler_args.iter())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
+            .process_group(0)
             .spawn()?;
 
-        match child.wait_timeout(COMPILE_TIMEOUT)? {
+        match self.wait_compile(&mut child)? {
             Some(status) => {
                 if status.success() {
                     let output = child.wait_with_output()?;
@@ -193,11 +209,47 @@ impl Compiler {
                 }
             }
             None => {
-                child.kill()?;
+                kill_compile(&mut child)?;
                 let error_output = "Compile process timed out".to_string();
                 log::error!("Compile error: {}", error_output);
                 Err(JudgeCoreError::CompileError(error_output))
             }
         }
     }
+
+    /// Wait up to `COMPILE_TIMEOUT` for the compile to exit, `None` if it is still running.
+    /// The token is checked every `COMPILE_CANCEL_CHECK_INTERVAL` in the meantime.
+    fn wait_compile(&self, child: &mut Child) -> Result<Option<ExitStatus>, JudgeCoreError> {
+        let deadline = Instant::now() + COMPILE_TIMEOUT;
+        loop {
+            if let Some(cancellation_token) = &self.cancellation_token {
+                if cancellation_token.is_cancelled() {
+                    log::info!("Compile pid={} is cancelled", child.id());
+                    kill_compile(child)?;
+                    return Err(JudgeCoreError::Cancelled);
+                }
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            let timeout = match &self.cancellation_token {
+                Some(_) => COMPILE_CANCEL_CHECK_INTERVAL.min(deadline - now),
+                None => deadline - now,
+            };
+            if let Some(status) = child.wait_timeout(timeout)? {
+                return Ok(Some(status));
+            }
+        }
+    }
+}
+
+/// Kill the process group of the compile, then reap it.
+fn kill_compile(child: &mut Child) -> Result<(), JudgeCoreError> {
+    match killpg(Pid::from_raw(child.id() as i32), Signal::SIGKILL) {
+        Ok(()) | Err(nix::errno::Errno::ESRCH) => {}
+        Err(e) => return Err(JudgeCoreError::NixErrno(e)),
+    }
+    child.wait()?;
+    Ok(())
 }
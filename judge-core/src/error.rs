@@ -1,3 +1,4 @@
+use crate::sandbox::spawn::SpawnStage;
 use libseccomp::error::SeccompError;
 use nix::errno::Errno;
 use std::ffi::NulError;
@@ -15,6 +16,10 @@ pub enum JudgeCoreError {
     AnyhowError(anyhow::Error),
     FromUtf8Error(FromUtf8Error),
     CompileError(String),
+    /// Cancelled by a `CancellationToken`, the judge has no result
+    Cancelled,
+    /// The sandboxed process failed at the stage with the errno before running the program
+    SpawnFailed(SpawnStage, Errno),
 }
 
 impl From<Errno> for JudgeCoreError {
@@ -5,9 +5,18 @@ use std::{fs, path::PathBuf};
 use crate::{
     compiler::{Compiler, Language},
     error::{path_not_exist, JudgeCoreError},
-    judge::{CheckerConfig, ProgramConfig, RuntimeConfig, TestdataConfig},
+    judge::{
+        CheckerConfig, ProgramConfig, RuntimeConfig, TestdataConfig, TleRerunConfig,
+        DEFAULT_STDERR_LIMIT_BYTES,
+    },
     package::PackageType,
-    run::executor::Executor,
+    run::{backend::SandboxBackendKind, executor::Executor},
+    sandbox::{
+        cancellation::CancellationToken,
+        jail::{JailConfigs, JAIL_SCRATCH_PATH},
+        namespace::NamespaceConfigs,
+        seccomp::get_seccomp_profiles,
+    },
 };
 
 #[derive(Debug, Clone, Serialize)]
@@ -31,6 +40,11 @@ pub struct JudgeBuilderInput {
     pub runtime_path: PathBuf,
     pub src_language: Language,
     pub src_path: PathBuf,
+    /// Name of the seccomp profile to use in place of the one required by the package,
+    /// the profile of `src_language` is used when neither is set
+    pub seccomp_profile: Option<String>,
+    /// Cancels compiling as well as the runs, see `RuntimeConfig::cancellation_token`
+    pub cancellation_token: Option<CancellationToken>,
 }
 
 impl JudgeBuilder {
@@ -53,16 +67,45 @@ impl JudgeBuilder {
 
         let rlimit_config = package_agent.get_rlimit_configs()?;
         log::info!("rlimit read {:?}", rlimit_config);
+        let seccomp_profile_name = match input.seccomp_profile {
+            Some(name) => Some(name),
+            None => package_agent.get_seccomp_profile_name()?,
+        };
+        let seccomp_profile = match &seccomp_profile_name {
+            Some(name) => get_seccomp_profiles().resolve(name)?,
+            None => get_seccomp_profiles().resolve_for_language(input.src_language)?,
+        };
         let runtime_config = RuntimeConfig {
             rlimit_configs: rlimit_config,
+            namespace_configs: NamespaceConfigs::all(),
+            jail_configs: Some(JailConfigs::default()),
+            seccomp_profile: Some(seccomp_profile),
+            tle_rerun: Some(TleRerunConfig::default()),
+            usage_sample_interval: None,
+            deterministic: false,
+            sandbox_backend: SandboxBackendKind::default(),
+            cancellation_token: input.cancellation_token.clone(),
         };
 
         let program_config = if input.src_path.exists() {
-            let compiler = Compiler::new(input.src_language, vec![]);
+            let mut compiler = Compiler::new(input.src_language, vec![]);
+            compiler.set_cancellation_token(input.cancellation_token);
             compiler.compile(&input.src_path, &input.runtime_path.join("program"))?;
+            let mut executor =
+                Executor::new(input.src_language, input.runtime_path.join("program"))?;
+            // The scratch dir is the only writable place in the jail,
+            // without one the runtime path is the run's own
+            let scratch_dir = match runtime_config.jail_configs {
+                Some(_) => PathBuf::from(JAIL_SCRATCH_PATH),
+                None => input.runtime_path.clone(),
+            };
+            executor.set_scratch_dir(scratch_dir);
+            executor.apply_configs(&package_agent.get_executor_configs()?);
             ProgramConfig {
-                executor: Executor::new(input.src_language, input.runtime_path.join("program"))?,
+                executor,
                 output_file_path: input.runtime_path.join("program.out"),
+                stderr_file_path: input.runtime_path.join("program.err"),
+                stderr_limit_bytes: DEFAULT_STDERR_LIMIT_BYTES,
             }
         } else {
             return Err(path_not_exist(&input.src_path));
@@ -1,21 +1,104 @@
+use crate::error::JudgeCoreError;
 use crate::judge::result::{
-    check_checker_result, check_user_result, get_max_mem, get_run_time, JudgeResultInfo,
+    check_checker_result, check_user_result, get_crash_reason, get_max_mem, get_run_time,
+    is_borderline_tle, JudgeResultInfo, RunAttempt,
 };
-use crate::sandbox::SCRIPT_LIMIT_CONFIG;
-use crate::utils::{compare_files, get_pathbuf_str};
-use crate::{error::JudgeCoreError, run::sandbox::ExecutorSandbox};
+use crate::run::backend::{SandboxBackend, SandboxSpec};
+use crate::sandbox::{SandboxExitInfo, SCRIPT_LIMIT_CONFIG};
+use crate::utils::{compare_files, get_pathbuf_str, read_file_head};
 
 use super::result::JudgeVerdict;
 use super::JudgeConfig;
 
+use nix::fcntl::OFlag;
+use nix::unistd::pipe2;
 use std::fs::File;
+use std::future::{ready, Future};
+use std::io::{self, Read, Write};
+use std::os::fd::OwnedFd;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::PathBuf;
-use std::time::Duration;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
 
-fn run_user(
-    config: &JudgeConfig,
-) -> Result<(Option<JudgeVerdict>, Duration, i64, i32), JudgeCoreError> {
+/// stderr of the user program goes through a pipe drained by a thread of the judger,
+/// which keeps the first `ProgramConfig::stderr_limit_bytes` in the stderr file
+/// and discards the rest.
+/// Unlike a file, the pipe does not count toward `RLIMIT_FSIZE`,
+/// so writing a lot to stderr is not an output limit exceeded.
+pub(crate) struct StderrCapture {
+    // Closed once the process is spawned, so that the thread sees the end of the pipe
+    write_end: Option<OwnedFd>,
+    drainer: JoinHandle<io::Result<()>>,
+}
+
+impl StderrCapture {
+    pub(crate) fn new(config: &JudgeConfig) -> Result<Self, JudgeCoreError> {
+        let mut stderr_file = File::create(&config.program.stderr_file_path)?;
+        // Close-on-exec, so that only the redirected copy is inherited by the program
+        let (read_end, write_end) = pipe2(OFlag::O_CLOEXEC)?;
+        let limit = config.program.stderr_limit_bytes;
+        let drainer = thread::spawn(move || {
+            let mut read_end = File::from(read_end);
+            let mut kept = 0u64;
+            let mut buf = [0u8; 4096];
+            loop {
+                let len = match read_end.read(&mut buf) {
+                    Ok(0) => return Ok(()),
+                    Ok(len) => len,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                };
+                let keep = (limit - kept).min(len as u64) as usize;
+                stderr_file.write_all(&buf[..keep])?;
+                kept += keep as u64;
+            }
+        });
+        Ok(Self {
+            write_end: Some(write_end),
+            drainer,
+        })
+    }
+
+    /// The fd to redirect stderr to, `None` once `close_write_end()` is called.
+    pub(crate) fn as_raw_fd(&self) -> Option<RawFd> {
+        self.write_end
+            .as_ref()
+            .map(|write_end| write_end.as_raw_fd())
+    }
+
+    /// Called once the process is spawned with its own copy of the write end.
+    pub(crate) fn close_write_end(&mut self) {
+        self.write_end = None;
+    }
+
+    /// Wait for the stderr file to be complete, which is when every process
+    /// of the program has exited, i.e. after the sandbox is waited.
+    pub(crate) fn finish(mut self) {
+        self.close_write_end();
+        match self.drainer.join() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => log::warn!("Failed to capture user stderr: {:?}", e),
+            Err(_) => log::warn!("Failed to capture user stderr: drainer panicked"),
+        }
+    }
+}
+
+/// The spawned sandboxes are named types rather than a bare `Box<dyn SandboxBackend>`
+/// (or a tuple of it), which is not proven `Send` for every lifetime
+/// when returned through `WaitStrategy::unblock()`.
+struct UserSandbox {
+    sandbox: Box<dyn SandboxBackend>,
+    // The stderr file is complete once it is finished
+    stderr_capture: StderrCapture,
+}
+
+struct CheckerSandbox(Box<dyn SandboxBackend>);
+
+/// The files are only needed until the process is spawned,
+/// since the child process has its own copies of the redirected fds.
+fn spawn_user(config: &JudgeConfig) -> Result<UserSandbox, JudgeCoreError> {
     let input_file = File::open(&config.test_data.input_file_path)?;
 
     if !config.program.output_file_path.exists() {
@@ -26,37 +109,216 @@ fn run_user(
         .truncate(true) // Overwrite the whole content of this file
         .open(&config.program.output_file_path)?;
 
+    let mut stderr_capture = StderrCapture::new(config)?;
+
     let input_raw_fd: RawFd = input_file.as_raw_fd();
     let program_output_raw_fd: RawFd = program_output_file.as_raw_fd();
 
     let user_executor = config.program.executor.clone();
-    let mut user_sandbox = ExecutorSandbox::new(
-        user_executor,
-        config.runtime.rlimit_configs.clone(),
-        Some(input_raw_fd),
-        Some(program_output_raw_fd),
-        true,
-    )?;
+    let mut user_sandbox = config.runtime.sandbox_backend.create(SandboxSpec {
+        input_redirect: Some(input_raw_fd),
+        output_redirect: Some(program_output_raw_fd),
+        error_redirect: stderr_capture.as_raw_fd(),
+        seccomp_profile: Some(config.get_user_seccomp_profile()?),
+        namespace_configs: config.runtime.namespace_configs,
+        jail_configs: config.runtime.jail_configs.clone(),
+        deterministic: config.runtime.deterministic,
+        usage_sample_interval: config.runtime.usage_sample_interval,
+        cancellation_token: config.runtime.cancellation_token.clone(),
+        ..SandboxSpec::new(user_executor, config.runtime.rlimit_configs.clone())
+    })?;
 
     log::debug!("Spawning user process");
     let _user_spawn = user_sandbox.spawn()?;
+    stderr_capture.close_write_end();
+    Ok(UserSandbox {
+        sandbox: user_sandbox,
+        stderr_capture,
+    })
+}
+
+/// How the sandboxes are waited for, the only difference between `run_judge()`
+/// and `run_judge_async()`, which share `judge()`.
+trait WaitStrategy {
+    /// Run `f`, which blocks the thread (e.g. spawning, or reading the files of the run).
+    fn unblock<T, F>(f: F) -> impl Future<Output = Result<T, JudgeCoreError>> + Send
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T, JudgeCoreError> + Send + 'static;
+
+    fn wait(
+        sandbox: &mut dyn SandboxBackend,
+    ) -> impl Future<Output = Result<SandboxExitInfo, JudgeCoreError>> + Send + '_;
+}
+
+/// Blocks the calling thread, so its futures are ready once polled, see `block_on_ready()`.
+struct BlockingWait;
+
+impl WaitStrategy for BlockingWait {
+    fn unblock<T, F>(f: F) -> impl Future<Output = Result<T, JudgeCoreError>> + Send
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T, JudgeCoreError> + Send + 'static,
+    {
+        ready(f())
+    }
+
+    fn wait(
+        sandbox: &mut dyn SandboxBackend,
+    ) -> impl Future<Output = Result<SandboxExitInfo, JudgeCoreError>> + Send + '_ {
+        ready(sandbox.wait())
+    }
+}
+
+/// Waits on the tokio runtime, and runs the blocking parts on its blocking threads.
+#[cfg(feature = "async")]
+struct AsyncWait;
+
+#[cfg(feature = "async")]
+impl WaitStrategy for AsyncWait {
+    async fn unblock<T, F>(f: F) -> Result<T, JudgeCoreError>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T, JudgeCoreError> + Send + 'static,
+    {
+        tokio::task::spawn_blocking(f).await.map_err(|e| {
+            JudgeCoreError::AnyhowError(anyhow::anyhow!("Blocking judge task failed: {:?}", e))
+        })?
+    }
+
+    fn wait(
+        sandbox: &mut dyn SandboxBackend,
+    ) -> impl Future<Output = Result<SandboxExitInfo, JudgeCoreError>> + Send + '_ {
+        sandbox.wait_async()
+    }
+}
+
+/// Drive a future of `BlockingWait` to completion on the calling thread.
+fn block_on_ready<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    match future
+        .as_mut()
+        .poll(&mut Context::from_waker(Waker::noop()))
+    {
+        Poll::Ready(output) => output,
+        Poll::Pending => unreachable!("Blocking judging never waits for a wake-up"),
+    }
+}
+
+/// The verdict of one run of the user program, with its result and the head of its stderr.
+type UserRun = (Option<JudgeVerdict>, SandboxExitInfo, Option<String>);
+
+async fn run_user<W: WaitStrategy>(config: &JudgeConfig) -> Result<UserRun, JudgeCoreError> {
+    let spawn_config = config.clone();
+    let UserSandbox {
+        mut sandbox,
+        stderr_capture,
+    } = W::unblock(move || spawn_user(&spawn_config)).await?;
     log::debug!("Waiting for user process");
-    let user_result = user_sandbox.wait()?;
-    let user_time = get_run_time(&user_result);
-    let max_mem = get_max_mem(&user_result);
-    Ok((
-        check_user_result(config, &user_result),
-        user_time,
-        max_mem,
-        user_result.exit_status,
-    ))
+    let user_result = W::wait(sandbox.as_mut()).await?;
+    let check_config = config.clone();
+    W::unblock(move || {
+        stderr_capture.finish();
+        let stderr = read_user_stderr(&check_config);
+        let user_verdict = check_user_result(&check_config, &user_result, stderr.as_deref());
+        Ok((user_verdict, user_result, stderr))
+    })
+    .await
 }
 
-pub fn run_checker(config: &JudgeConfig) -> Result<(JudgeVerdict, i32), JudgeCoreError> {
+/// Re-runs of a borderline time limit result, see `TleRerunConfig`.
+/// They stop as soon as the aggregated time is decided, so that the output file is left
+/// by the last attempt, which is the one returned.
+struct TleReruns {
+    attempts: Vec<RunAttempt>,
+    required_passes: u32,
+    allowed_failures: u32,
+    passes: u32,
+    failures: u32,
+}
+
+impl TleReruns {
+    /// `None` if the first run needs no re-run.
+    fn new(
+        config: &JudgeConfig,
+        user_verdict: &Option<JudgeVerdict>,
+        user_result: &SandboxExitInfo,
+    ) -> Option<Self> {
+        let tle_rerun = match config.runtime.tle_rerun {
+            Some(tle_rerun)
+                if *user_verdict == Some(JudgeVerdict::TimeLimitExceeded)
+                    && is_borderline_tle(config, user_result, tle_rerun.margin_percent) =>
+            {
+                tle_rerun
+            }
+            _ => return None,
+        };
+        let required_passes = tle_rerun
+            .time_aggregate
+            .get_required_passes(tle_rerun.max_attempts);
+        Some(Self {
+            attempts: vec![RunAttempt::new(user_verdict, user_result)],
+            required_passes,
+            allowed_failures: tle_rerun.max_attempts.saturating_sub(required_passes),
+            passes: 0,
+            failures: 1,
+        })
+    }
+
+    fn is_decided(&self) -> bool {
+        self.passes >= self.required_passes || self.failures > self.allowed_failures
+    }
+
+    fn record(&mut self, user_verdict: &Option<JudgeVerdict>, user_result: &SandboxExitInfo) {
+        self.attempts
+            .push(RunAttempt::new(user_verdict, user_result));
+        if *user_verdict == Some(JudgeVerdict::TimeLimitExceeded) {
+            self.failures += 1;
+        } else {
+            self.passes += 1;
+        }
+    }
+}
+
+/// Re-run the user program while a time limit result is borderline, see `TleReruns`.
+async fn run_user_with_reruns<W: WaitStrategy>(
+    config: &JudgeConfig,
+) -> Result<(UserRun, Vec<RunAttempt>), JudgeCoreError> {
+    let (mut user_verdict, mut user_result, mut stderr) = run_user::<W>(config).await?;
+    let mut reruns = match TleReruns::new(config, &user_verdict, &user_result) {
+        Some(reruns) => reruns,
+        None => return Ok(((user_verdict, user_result, stderr), vec![])),
+    };
+    while !reruns.is_decided() {
+        log::debug!(
+            "Re-running borderline TLE, attempt {}",
+            reruns.attempts.len() + 1
+        );
+        (user_verdict, user_result, stderr) = run_user::<W>(config).await?;
+        reruns.record(&user_verdict, &user_result);
+    }
+    log::debug!("Borderline TLE attempts: {:?}", reruns.attempts);
+    Ok(((user_verdict, user_result, stderr), reruns.attempts))
+}
+
+/// The head of the user program's stderr, see `ProgramConfig::stderr_limit_bytes`.
+pub fn read_user_stderr(config: &JudgeConfig) -> Option<String> {
+    match read_file_head(
+        &config.program.stderr_file_path,
+        config.program.stderr_limit_bytes,
+    ) {
+        Ok(stderr) if !stderr.is_empty() => Some(stderr),
+        Ok(_) => None,
+        Err(e) => {
+            log::warn!("Failed to read user stderr: {:?}", e);
+            None
+        }
+    }
+}
+
+fn spawn_checker(config: &JudgeConfig) -> Result<CheckerSandbox, JudgeCoreError> {
     if let Some(mut checker_executor) = config.checker.executor.clone() {
-        let first_args = String::from("");
         let checker_args = vec![
-            first_args,
             get_pathbuf_str(&config.test_data.input_file_path)?,
             get_pathbuf_str(&config.program.output_file_path)?,
             get_pathbuf_str(&config.test_data.answer_file_path)?,
@@ -64,22 +326,14 @@ pub fn run_checker(config: &JudgeConfig) -> Result<(JudgeVerdict, i32), JudgeCor
         ];
         checker_executor.set_additional_args(checker_args);
 
-        let mut checker_process = ExecutorSandbox::new(
-            checker_executor,
-            SCRIPT_LIMIT_CONFIG.clone(),
-            None,
-            None,
-            false,
-        )?;
+        let mut checker_process = config.runtime.sandbox_backend.create(SandboxSpec {
+            cancellation_token: config.runtime.cancellation_token.clone(),
+            ..SandboxSpec::new(checker_executor, SCRIPT_LIMIT_CONFIG.clone())
+        })?;
 
         log::debug!("Spawning checker process");
         let _checker_spawn = checker_process.spawn()?;
-        log::debug!("Waiting for checker process");
-        let checker_result = checker_process.wait()?;
-        Ok((
-            check_checker_result(&checker_result),
-            checker_result.exit_status,
-        ))
+        Ok(CheckerSandbox(checker_process))
     } else {
         Err(JudgeCoreError::AnyhowError(anyhow::anyhow!(
             "Checker executor is not set"
@@ -87,46 +341,200 @@ pub fn run_checker(config: &JudgeConfig) -> Result<(JudgeVerdict, i32), JudgeCor
     }
 }
 
-pub fn run_judge(config: &JudgeConfig) -> Result<JudgeResultInfo, JudgeCoreError> {
-    let (user_verdict, user_time, max_mem, user_exit_status) = run_user(config)?;
-    if let Some(verdict) = user_verdict {
-        return Ok(JudgeResultInfo {
-            verdict,
-            time_usage: user_time,
-            memory_usage_bytes: max_mem,
-            exit_status: user_exit_status,
-            checker_exit_status: 0,
-        });
-    }
+pub fn run_checker(config: &JudgeConfig) -> Result<(JudgeVerdict, i32), JudgeCoreError> {
+    let CheckerSandbox(checker_process) = spawn_checker(config)?;
+    log::debug!("Waiting for checker process");
+    let checker_result = checker_process.wait()?;
+    Ok((
+        check_checker_result(&checker_result),
+        checker_result.exit_status,
+    ))
+}
 
-    log::debug!("Creating sandbox for checker process");
-    if let Some(_checker_executor) = config.checker.executor.clone() {
-        let (verdict, checker_exit_status) = run_checker(config)?;
-        Ok(JudgeResultInfo {
-            verdict,
-            time_usage: user_time,
-            memory_usage_bytes: max_mem,
-            exit_status: user_exit_status,
-            checker_exit_status,
+/// The result of the user program, with `Accepted` unless the program failed.
+fn get_user_result_info(
+    config: &JudgeConfig,
+    user_verdict: &Option<JudgeVerdict>,
+    user_result: SandboxExitInfo,
+    stderr: Option<String>,
+    attempts: Vec<RunAttempt>,
+) -> JudgeResultInfo {
+    let time_usage = config
+        .runtime
+        .tle_rerun
+        .and_then(|tle_rerun| {
+            let times = attempts
+                .iter()
+                .map(|attempt| attempt.time_usage)
+                .collect::<Vec<_>>();
+            tle_rerun.time_aggregate.aggregate(&times)
         })
-    } else if compare_files(
+        .unwrap_or_else(|| get_run_time(&user_result));
+    JudgeResultInfo {
+        verdict: user_verdict.clone().unwrap_or(JudgeVerdict::Accepted),
+        time_usage,
+        real_time_usage: user_result.real_time_cost,
+        memory_usage_bytes: get_max_mem(&user_result),
+        exit_status: user_result.exit_status,
+        checker_exit_status: 0,
+        restricted_syscall: user_result.exit_reason.get_restricted_syscall(),
+        crash_reason: get_crash_reason(user_verdict, &user_result, &stderr),
+        stderr,
+        attempts,
+        usage_samples: user_result.usage_samples,
+    }
+}
+
+/// Judge the output by comparing with the answer when there is no checker.
+fn compare_output(config: &JudgeConfig, result_info: JudgeResultInfo) -> JudgeResultInfo {
+    if compare_files(
         &PathBuf::from(&config.program.output_file_path),
         &PathBuf::from(&config.test_data.answer_file_path),
     ) {
-        Ok(JudgeResultInfo {
+        JudgeResultInfo {
             verdict: JudgeVerdict::Accepted,
-            time_usage: user_time,
-            memory_usage_bytes: max_mem,
-            exit_status: user_exit_status,
-            checker_exit_status: 0,
-        })
+            ..result_info
+        }
     } else {
-        Ok(JudgeResultInfo {
+        JudgeResultInfo {
             verdict: JudgeVerdict::WrongAnswer,
-            time_usage: user_time,
-            memory_usage_bytes: max_mem,
-            exit_status: user_exit_status,
-            checker_exit_status: 0,
+            ..result_info
+        }
+    }
+}
+
+async fn judge<W: WaitStrategy>(config: &JudgeConfig) -> Result<JudgeResultInfo, JudgeCoreError> {
+    let ((user_verdict, user_result, stderr), attempts) = run_user_with_reruns::<W>(config).await?;
+    let result_info = get_user_result_info(config, &user_verdict, user_result, stderr, attempts);
+    if user_verdict.is_some() {
+        return Ok(result_info);
+    }
+
+    log::debug!("Creating sandbox for checker process");
+    if let Some(_checker_executor) = config.checker.executor.clone() {
+        let checker_config = config.clone();
+        let CheckerSandbox(mut checker_process) =
+            W::unblock(move || spawn_checker(&checker_config)).await?;
+        log::debug!("Waiting for checker process");
+        let checker_result = W::wait(checker_process.as_mut()).await?;
+        Ok(JudgeResultInfo {
+            verdict: check_checker_result(&checker_result),
+            checker_exit_status: checker_result.exit_status,
+            ..result_info
         })
+    } else {
+        let compare_config = config.clone();
+        W::unblock(move || Ok(compare_output(&compare_config, result_info))).await
     }
 }
+
+pub fn run_judge(config: &JudgeConfig) -> Result<JudgeResultInfo, JudgeCoreError> {
+    block_on_ready(judge::<BlockingWait>(config))
+}
+
+/// The async counterpart of `run_judge()`, which does not block the tokio runtime:
+/// the sandboxed processes are waited for on it, and the rest runs on its blocking threads.
+#[cfg(feature = "async")]
+pub async fn run_judge_async(config: &JudgeConfig) -> Result<JudgeResultInfo, JudgeCoreError> {
+    judge::<AsyncWait>(config).await
+}
+
+#[test]
+fn test_tle_reruns() {
+    use super::{
+        CheckerConfig, ProgramConfig, RuntimeConfig, TestdataConfig, TimeAggregate, TleRerunConfig,
+    };
+    use crate::compiler::Language;
+    use crate::run::{backend::SandboxBackendKind, executor::Executor};
+    use crate::sandbox::{namespace::NamespaceConfigs, ExitReason, Rusage, DEFAULT_RLIMIT_CONFIGS};
+    use std::time::Duration;
+
+    // The time limit is a second
+    let config = |time_aggregate| JudgeConfig {
+        test_data: TestdataConfig {
+            input_file_path: PathBuf::from("0.in"),
+            answer_file_path: PathBuf::from("0.ans"),
+        },
+        runtime: RuntimeConfig {
+            rlimit_configs: DEFAULT_RLIMIT_CONFIGS.clone(),
+            namespace_configs: NamespaceConfigs::default(),
+            jail_configs: None,
+            seccomp_profile: None,
+            tle_rerun: Some(TleRerunConfig {
+                margin_percent: 10,
+                max_attempts: 3,
+                time_aggregate,
+            }),
+            usage_sample_interval: None,
+            deterministic: false,
+            sandbox_backend: SandboxBackendKind::Native,
+            cancellation_token: None,
+        },
+        program: ProgramConfig {
+            executor: Executor::new(Language::Cpp, PathBuf::from("/bin/true")).unwrap(),
+            output_file_path: PathBuf::from("program.out"),
+            stderr_file_path: PathBuf::from("program.err"),
+            stderr_limit_bytes: 0,
+        },
+        checker: CheckerConfig {
+            executor: None,
+            output_file_path: PathBuf::from("checker.out"),
+        },
+    };
+    let raw_info = |millis| SandboxExitInfo {
+        exit_status: 0,
+        exit_reason: ExitReason::Exited(0),
+        real_time_cost: Duration::from_millis(millis),
+        resource_usage: Rusage {
+            user_time: Duration::from_millis(millis),
+            system_time: Duration::ZERO,
+            max_rss: 0,
+            page_faults: 0,
+            involuntary_context_switches: 0,
+            voluntary_context_switches: 0,
+        },
+        usage_samples: vec![],
+        learned_syscalls: vec![],
+    };
+    let tle = Some(JudgeVerdict::TimeLimitExceeded);
+
+    let median = config(TimeAggregate::Median);
+    // Far past the limit, or within it
+    assert!(TleReruns::new(&median, &tle, &raw_info(1500)).is_none());
+    assert!(TleReruns::new(&median, &None, &raw_info(900)).is_none());
+
+    // The median of 3 attempts is decided by 2 passes or 2 failures
+    let mut reruns = TleReruns::new(&median, &tle, &raw_info(1050)).unwrap();
+    assert!(!reruns.is_decided());
+    reruns.record(&None, &raw_info(950));
+    assert!(!reruns.is_decided());
+    reruns.record(&None, &raw_info(980));
+    assert!(reruns.is_decided());
+    let result_info = get_user_result_info(&median, &None, raw_info(980), None, reruns.attempts);
+    assert_eq!(result_info.verdict, JudgeVerdict::Accepted);
+    assert_eq!(result_info.time_usage, Duration::from_millis(980));
+
+    let mut reruns = TleReruns::new(&median, &tle, &raw_info(1050)).unwrap();
+    reruns.record(&tle, &raw_info(1080));
+    assert!(reruns.is_decided());
+    let result_info = get_user_result_info(&median, &tle, raw_info(1080), None, reruns.attempts);
+    assert_eq!(result_info.verdict, JudgeVerdict::TimeLimitExceeded);
+    assert_eq!(result_info.time_usage, Duration::from_millis(1050));
+
+    // The min is decided by a single pass, or once every attempt has failed
+    let min = config(TimeAggregate::Min);
+    let mut reruns = TleReruns::new(&min, &tle, &raw_info(1050)).unwrap();
+    reruns.record(&None, &raw_info(990));
+    assert!(reruns.is_decided());
+    let result_info = get_user_result_info(&min, &None, raw_info(990), None, reruns.attempts);
+    assert_eq!(result_info.time_usage, Duration::from_millis(990));
+
+    let mut reruns = TleReruns::new(&min, &tle, &raw_info(1050)).unwrap();
+    reruns.record(&tle, &raw_info(1070));
+    assert!(!reruns.is_decided());
+    reruns.record(&tle, &raw_info(1060));
+    assert!(reruns.is_decided());
+    assert_eq!(reruns.attempts.len(), 3);
+    let result_info = get_user_result_info(&min, &tle, raw_info(1060), None, reruns.attempts);
+    assert_eq!(result_info.time_usage, Duration::from_millis(1050));
+}
@@ -1,9 +1,11 @@
 use crate::error::JudgeCoreError;
-use crate::judge::common::run_checker;
-use crate::judge::result::{check_user_result, JudgeVerdict};
+use crate::judge::common::{read_user_stderr, run_checker, StderrCapture};
+use crate::judge::result::{
+    check_checker_result, check_user_result, get_crash_reason, get_max_mem, get_run_time,
+    JudgeVerdict,
+};
+use crate::run::backend::{SandboxBackend, SandboxSpec};
 use crate::run::executor::Executor;
-use crate::run::process_listener::{ProcessExitMessage, ProcessListener};
-use crate::run::sandbox::ExecutorSandbox;
 use crate::sandbox::{SandboxExitInfo, SCRIPT_LIMIT_CONFIG};
 use crate::utils::get_pathbuf_str;
 
@@ -15,14 +17,10 @@ use std::fs::File;
 use std::os::fd::BorrowedFd;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::PathBuf;
-use std::time::Duration;
 
 use super::result::JudgeResultInfo;
 use super::JudgeConfig;
 
-const USER_EXIT_SIGNAL: u8 = 41u8;
-const INTERACTOR_EXIT_SIGNAL: u8 = 42u8;
-
 fn set_fd_non_blocking(fd: RawFd) -> Result<libc::c_int, JudgeCoreError> {
     log::debug!("Setting fd={} to non blocking", fd);
     Ok(fcntl(fd, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?)
@@ -54,49 +52,46 @@ fn pump_proxy_pipe(from: RawFd, to: RawFd, output: RawFd) -> Result<(), JudgeCor
     }
 }
 
-/// `from` will be set to non-blocking mode.
-fn read_string_from_fd(from: RawFd) -> Result<String, JudgeCoreError> {
-    set_fd_non_blocking(from)?;
-
-    let mut res_buf = Vec::new();
-    let mut buf = [0; 1024];
-    log::debug!("Reading from fd={}", from);
-    loop {
-        log::debug!("Reading from fd={}", from);
-        match read(from, &mut buf) {
-            Ok(nread) => {
-                log::debug!("{} read. {}", nread, from);
-                res_buf.extend_from_slice(&buf[..nread]);
-            }
-            Err(e) => {
-                if e == Errno::EAGAIN || e == Errno::EWOULDBLOCK {
-                    let buf_string = String::from_utf8(res_buf)?;
-                    return Ok(buf_string);
-                }
-                panic!("failed to read from pipe");
-            }
-        }
-    }
-}
-
-fn read_msg_from_fd(from: RawFd) -> Result<ProcessExitMessage, JudgeCoreError> {
-    let buf_string = read_string_from_fd(from as RawFd)?;
-    log::debug!("Raw Result info: {}", buf_string);
-    let msg: ProcessExitMessage = serde_json::from_str(&buf_string)?;
-    Ok(msg)
-}
-
 fn add_epoll_fd(epoll: &Epoll, fd: RawFd) -> Result<(), JudgeCoreError> {
     let event = EpollEvent::new(EpollFlags::EPOLLIN, fd as u64);
     log::debug!("Adding fd={} to epoll", fd);
     Ok(epoll.add(unsafe { BorrowedFd::borrow_raw(fd) }, event)?)
 }
 
+/// A sandbox watched in the epoll loop through `SandboxBackend::get_event_fd()`.
+struct WatchedSandbox {
+    sandbox: Box<dyn SandboxBackend>,
+    result: Option<SandboxExitInfo>,
+}
+
+impl WatchedSandbox {
+    fn new(epoll: &Epoll, sandbox: Box<dyn SandboxBackend>) -> Result<Self, JudgeCoreError> {
+        add_epoll_fd(epoll, sandbox.get_event_fd()?.as_raw_fd())?;
+        Ok(Self {
+            sandbox,
+            result: None,
+        })
+    }
+
+    /// Poll the sandbox, and reap it once it exits.
+    /// Its event fd is removed from epoll then, since it stays readable.
+    fn poll_exit(&mut self, epoll: &Epoll) -> Result<(), JudgeCoreError> {
+        if self.result.is_some() || !self.sandbox.poll_exit()? {
+            return Ok(());
+        }
+        let event_fd = self.sandbox.get_event_fd()?;
+        log::debug!("Removing fd={} from epoll", event_fd.as_raw_fd());
+        epoll.delete(event_fd)?;
+        self.result = Some(self.sandbox.wait()?);
+        Ok(())
+    }
+}
+
 pub fn run_interact(
     config: &JudgeConfig,
     mut interactor_executor: Executor,
     output_path: &PathBuf,
-) -> Result<Option<JudgeResultInfo>, JudgeCoreError> {
+) -> Result<JudgeResultInfo, JudgeCoreError> {
     log::debug!("Creating epoll");
     let epoll = Epoll::new(EpollCreateFlags::EPOLL_CLOEXEC)?;
 
@@ -110,17 +105,6 @@ pub fn run_interact(
     add_epoll_fd(&epoll, proxy_read_user.as_raw_fd())?;
     add_epoll_fd(&epoll, proxy_read_interactor.as_raw_fd())?;
 
-    log::debug!("Creating exit report pipes with epoll");
-    let (user_exit_read, user_exit_write) = pipe()?;
-    let (interactor_exit_read, interactor_exit_write) = pipe()?;
-    add_epoll_fd(&epoll, user_exit_read.as_raw_fd())?;
-    add_epoll_fd(&epoll, interactor_exit_read.as_raw_fd())?;
-
-    let mut user_listener = ProcessListener::new()?;
-    let mut interact_listener = ProcessListener::new()?;
-    user_listener.setup_exit_report(user_exit_write.as_raw_fd(), USER_EXIT_SIGNAL);
-    interact_listener.setup_exit_report(interactor_exit_write.as_raw_fd(), INTERACTOR_EXIT_SIGNAL);
-
     if !PathBuf::from(&output_path).exists() {
         File::create(output_path)?;
     }
@@ -129,56 +113,55 @@ pub fn run_interact(
         .truncate(true) // Overwrite the whole content of this file
         .open(output_path)?;
     let output_raw_fd: RawFd = output_file.as_raw_fd();
+    let mut stderr_capture = StderrCapture::new(config)?;
+
+    let mut user_sandbox = config.runtime.sandbox_backend.create(SandboxSpec {
+        input_redirect: Some(user_read_proxy.as_raw_fd()),
+        output_redirect: Some(user_write_proxy.as_raw_fd()),
+        error_redirect: stderr_capture.as_raw_fd(),
+        seccomp_profile: Some(config.get_user_seccomp_profile()?),
+        namespace_configs: config.runtime.namespace_configs,
+        jail_configs: config.runtime.jail_configs.clone(),
+        deterministic: config.runtime.deterministic,
+        usage_sample_interval: config.runtime.usage_sample_interval,
+        // Not set on the interactor, which shares the epoll and is killed when judging fails
+        cancellation_token: config.runtime.cancellation_token.clone(),
+        ..SandboxSpec::new(
+            config.program.executor.clone(),
+            config.runtime.rlimit_configs.clone(),
+        )
+    })?;
+    user_sandbox.spawn()?;
+    stderr_capture.close_write_end();
+    let mut user = WatchedSandbox::new(&epoll, user_sandbox)?;
 
-    let mut user_sandbox = ExecutorSandbox::new(
-        config.program.executor.clone(),
-        config.runtime.rlimit_configs.clone(),
-        Some(user_read_proxy.as_raw_fd()),
-        Some(user_write_proxy.as_raw_fd()),
-        true,
-    )?;
-    user_listener.spawn_with_sandbox(&mut user_sandbox)?;
-
-    let first_args: String = String::from("");
     let interact_args = vec![
-        first_args,
         get_pathbuf_str(&config.test_data.input_file_path)?,
         get_pathbuf_str(&config.program.output_file_path)?,
         get_pathbuf_str(&config.test_data.answer_file_path)?,
     ];
     interactor_executor.set_additional_args(interact_args);
-    let mut interact_sandbox = ExecutorSandbox::new(
-        interactor_executor,
-        SCRIPT_LIMIT_CONFIG.clone(),
-        Some(interactor_read_proxy.as_raw_fd()),
-        Some(interactor_write_proxy.as_raw_fd()),
-        false,
-    )?;
-    interact_listener.spawn_with_sandbox(&mut interact_sandbox)?;
+    let mut interact_sandbox = config.runtime.sandbox_backend.create(SandboxSpec {
+        input_redirect: Some(interactor_read_proxy.as_raw_fd()),
+        output_redirect: Some(interactor_write_proxy.as_raw_fd()),
+        ..SandboxSpec::new(interactor_executor, SCRIPT_LIMIT_CONFIG.clone())
+    })?;
+    interact_sandbox.spawn()?;
+    let mut interactor = WatchedSandbox::new(&epoll, interact_sandbox)?;
 
     log::debug!("Starting epoll");
     let mut events = [EpollEvent::empty(); 128];
-    let mut user_exited = false;
-    let mut interactor_exited = false;
-    let mut option_user_result: Option<SandboxExitInfo> = None;
-    loop {
-        let num_events = epoll.wait(&mut events, EpollTimeout::NONE)?;
+    let (user_result, interactor_result) = loop {
+        let num_events = match epoll.wait(&mut events, EpollTimeout::NONE) {
+            Ok(num_events) => num_events,
+            Err(Errno::EINTR) => 0,
+            Err(e) => return Err(e.into()),
+        };
         log::debug!("{} events found!", num_events);
 
         for event in events.iter().take(num_events) {
             log::debug!("Event: {:?}", event);
             let fd = event.data() as RawFd;
-            if fd == user_exit_read.as_raw_fd() {
-                log::debug!("{:?} user fd exited", fd);
-                user_exited = true;
-                let exit_msg = read_msg_from_fd(fd)?;
-                option_user_result = exit_msg.option_run_result;
-            }
-            if fd == interactor_exit_read.as_raw_fd() {
-                log::debug!("{:?} interactor fd exited", fd);
-                interactor_exited = true;
-                let _interactor_result: ProcessExitMessage = read_msg_from_fd(fd)?;
-            }
             if fd == proxy_read_user.as_raw_fd() {
                 log::debug!("proxy_read_user {} fd read", fd);
                 pump_proxy_pipe(
@@ -196,47 +179,76 @@ pub fn run_interact(
                 )?;
             }
         }
-        if user_exited && interactor_exited {
-            log::debug!("Both user and interactor exited");
-            break;
+        user.poll_exit(&epoll)?;
+        interactor.poll_exit(&epoll)?;
+        match (user.result.take(), interactor.result.take()) {
+            (Some(user_result), Some(interactor_result)) => {
+                log::debug!("Both user and interactor exited");
+                break (user_result, interactor_result);
+            }
+            (user_result, interactor_result) => {
+                user.result = user_result;
+                interactor.result = interactor_result;
+            }
         }
-    }
+    };
     log::debug!("Epoll finished!");
-
-    if let Some(user_result) = option_user_result {
-        let option_user_verdict = check_user_result(config, &user_result);
-        if let Some(verdict) = option_user_verdict {
-            return Ok(Some(JudgeResultInfo {
-                verdict,
-                time_usage: user_result.real_time_cost,
-                memory_usage_bytes: user_result.resource_usage.max_rss,
-                exit_status: user_result.exit_status,
-                checker_exit_status: 0,
-            }));
-        }
-        log::debug!("Running checker process");
-        if let Some(_checker_executor) = config.checker.executor.clone() {
-            let (verdict, checker_exit_status) = run_checker(config)?;
-            Ok(Some(JudgeResultInfo {
-                verdict,
-                time_usage: user_result.real_time_cost,
-                memory_usage_bytes: user_result.resource_usage.max_rss,
-                exit_status: user_result.exit_status,
-                checker_exit_status,
-            }))
-        } else {
-            Err(JudgeCoreError::AnyhowError(anyhow::anyhow!(
-                "Checker path is not provided"
-            )))
-        }
-    } else {
-        // interactor output should be checked here
-        Ok(Some(JudgeResultInfo {
-            verdict: JudgeVerdict::IdlenessLimitExceeded,
-            time_usage: Duration::new(0, 0),
-            memory_usage_bytes: 0,
-            exit_status: 0,
+    stderr_capture.finish();
+    let stderr = read_user_stderr(config);
+
+    let option_user_verdict = check_user_result(config, &user_result, stderr.as_deref());
+    let crash_reason = get_crash_reason(&option_user_verdict, &user_result, &stderr);
+    if let Some(verdict) = option_user_verdict {
+        return Ok(JudgeResultInfo {
+            verdict,
+            time_usage: get_run_time(&user_result),
+            real_time_usage: user_result.real_time_cost,
+            memory_usage_bytes: get_max_mem(&user_result),
+            exit_status: user_result.exit_status,
             checker_exit_status: 0,
-        }))
+            restricted_syscall: user_result.exit_reason.get_restricted_syscall(),
+            crash_reason,
+            stderr,
+            attempts: vec![],
+            usage_samples: user_result.usage_samples,
+        });
+    }
+    // Interactors exit in the same way as checkers when rejecting the user program
+    let interactor_verdict = check_checker_result(&interactor_result);
+    if interactor_verdict != JudgeVerdict::Accepted {
+        return Ok(JudgeResultInfo {
+            verdict: interactor_verdict,
+            time_usage: get_run_time(&user_result),
+            real_time_usage: user_result.real_time_cost,
+            memory_usage_bytes: get_max_mem(&user_result),
+            exit_status: user_result.exit_status,
+            checker_exit_status: interactor_result.exit_status,
+            restricted_syscall: None,
+            crash_reason: None,
+            stderr,
+            attempts: vec![],
+            usage_samples: user_result.usage_samples,
+        });
+    }
+    log::debug!("Running checker process");
+    if let Some(_checker_executor) = config.checker.executor.clone() {
+        let (verdict, checker_exit_status) = run_checker(config)?;
+        Ok(JudgeResultInfo {
+            verdict,
+            time_usage: get_run_time(&user_result),
+            real_time_usage: user_result.real_time_cost,
+            memory_usage_bytes: get_max_mem(&user_result),
+            exit_status: user_result.exit_status,
+            checker_exit_status,
+            restricted_syscall: None,
+            crash_reason: None,
+            stderr,
+            attempts: vec![],
+            usage_samples: user_result.usage_samples,
+        })
+    } else {
+        Err(JudgeCoreError::AnyhowError(anyhow::anyhow!(
+            "Checker path is not provided"
+        )))
     }
 }
@@ -1,17 +1,95 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 use serde_derive::Serialize;
 
-use crate::{run::executor::Executor, sandbox::RlimitConfigs};
+use crate::{
+    error::JudgeCoreError,
+    run::{backend::SandboxBackendKind, executor::Executor},
+    sandbox::{
+        cancellation::CancellationToken,
+        jail::JailConfigs,
+        namespace::NamespaceConfigs,
+        seccomp::{get_seccomp_profiles, SeccompProfile},
+        RlimitConfigs,
+    },
+};
 
 pub mod builder;
 pub mod common;
 pub mod interact;
 pub mod result;
 
-#[derive(Debug, Clone, Serialize)]
+/// Enough for a traceback or a sanitizer report
+pub static DEFAULT_STDERR_LIMIT_BYTES: u64 = 16 * 1024;
+
+#[derive(Default, Debug, Clone, Serialize)]
 pub struct RuntimeConfig {
     pub rlimit_configs: RlimitConfigs,
+    /// Only applied to the user program, checkers and interactors are trusted
+    pub namespace_configs: NamespaceConfigs,
+    /// Only applied to the user program, `None` keeps the filesystem of the judger visible
+    pub jail_configs: Option<JailConfigs>,
+    /// Applied to the user program, `None` means using the profile of the program language
+    pub seccomp_profile: Option<SeccompProfile>,
+    /// `None` reports borderline time limit results as they are
+    pub tle_rerun: Option<TleRerunConfig>,
+    /// Sample the usage of the user program at this interval into `JudgeResultInfo::usage_samples`
+    pub usage_sample_interval: Option<Duration>,
+    /// Run the user program in deterministic mode, see `Sandbox::set_deterministic()`
+    pub deterministic: bool,
+    /// Runs the user program, checkers and interactors
+    pub sandbox_backend: SandboxBackendKind,
+    /// Cancelling it kills the running program, then judging fails with `JudgeCoreError::Cancelled`
+    #[serde(skip)]
+    pub cancellation_token: Option<CancellationToken>,
+}
+
+/// Re-running the user program when it exceeds the time limit by a small margin,
+/// so that noise from the machine does not turn into false TLEs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TleRerunConfig {
+    /// Runs using at most this percent more CPU time than the limit are borderline
+    pub margin_percent: u64,
+    /// Including the first run
+    pub max_attempts: u32,
+    pub time_aggregate: TimeAggregate,
+}
+
+impl Default for TleRerunConfig {
+    fn default() -> Self {
+        Self {
+            margin_percent: 10,
+            max_attempts: 3,
+            time_aggregate: TimeAggregate::Median,
+        }
+    }
+}
+
+/// How the CPU time of the attempts is turned into the time of the result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum TimeAggregate {
+    Min,
+    /// The lower median for an even number of attempts
+    Median,
+}
+
+impl TimeAggregate {
+    pub fn aggregate(&self, times: &[Duration]) -> Option<Duration> {
+        let mut times = times.to_vec();
+        times.sort();
+        match self {
+            Self::Min => times.first().copied(),
+            Self::Median => times.get(times.len().saturating_sub(1) / 2).copied(),
+        }
+    }
+
+    /// How many of `max_attempts` have to finish within the limit for the aggregated time to do so.
+    pub fn get_required_passes(&self, max_attempts: u32) -> u32 {
+        match self {
+            Self::Min => 1,
+            Self::Median => max_attempts / 2 + 1,
+        }
+    }
 }
 
 /// When `executor` is `None`, default checker will be used.
@@ -25,6 +103,11 @@ pub struct CheckerConfig {
 pub struct ProgramConfig {
     pub executor: Executor,
     pub output_file_path: PathBuf,
+    /// stderr is written here instead of the output, see `common::StderrCapture`
+    pub stderr_file_path: PathBuf,
+    /// At most this many bytes of stderr are kept in the file and carried in the judge result,
+    /// the rest is discarded without counting toward `RLIMIT_FSIZE`
+    pub stderr_limit_bytes: u64,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -40,3 +123,27 @@ pub struct JudgeConfig {
     pub program: ProgramConfig,
     pub checker: CheckerConfig,
 }
+
+impl JudgeConfig {
+    pub fn get_user_seccomp_profile(&self) -> Result<SeccompProfile, JudgeCoreError> {
+        match &self.runtime.seccomp_profile {
+            Some(seccomp_profile) => Ok(seccomp_profile.clone()),
+            None => get_seccomp_profiles().resolve_for_language(self.program.executor.language),
+        }
+    }
+}
+
+#[test]
+fn test_time_aggregate() {
+    let times = [3, 1, 2, 4].map(Duration::from_millis);
+    assert_eq!(
+        TimeAggregate::Min.aggregate(&times),
+        Some(Duration::from_millis(1))
+    );
+    assert_eq!(
+        TimeAggregate::Median.aggregate(&times),
+        Some(Duration::from_millis(2))
+    );
+    assert_eq!(TimeAggregate::Median.aggregate(&[]), None);
+    assert_eq!(TimeAggregate::Median.get_required_passes(3), 2);
+}
@@ -1,18 +1,53 @@
 use serde_derive::Serialize;
 
-use std::{fmt, ops::Add, time::Duration};
+use std::{fmt, fs, ops::Add, time::Duration};
 
-use crate::sandbox::SandboxExitInfo;
+use crate::cgroup::get_cgroup_runs_root;
+use crate::sandbox::{usage::UsageSample, CrashReason, ExitReason, SandboxExitInfo};
 
 use super::JudgeConfig;
 
 #[derive(Debug, Serialize, Clone)]
 pub struct JudgeResultInfo {
     pub verdict: JudgeVerdict,
+    /// CPU time across all threads
     pub time_usage: Duration,
+    /// Wall clock time, which is less than `time_usage` for parallel programs
+    pub real_time_usage: Duration,
     pub memory_usage_bytes: i64,
     pub exit_status: i32,
     pub checker_exit_status: i32,
+    /// Name of the syscall when the verdict is `RestrictedFunction`,
+    /// `None` if the sandbox could not tell which syscall it was
+    pub restricted_syscall: Option<String>,
+    /// Why the user program crashed when the verdict is `RuntimeError`
+    pub crash_reason: Option<CrashReason>,
+    /// Head of the user program's stderr, `None` if nothing was written
+    pub stderr: Option<String>,
+    /// Every run of the user program when a borderline time limit result was re-run,
+    /// empty otherwise. `time_usage` is then aggregated from these.
+    pub attempts: Vec<RunAttempt>,
+    /// Usage of the user program over time, see `RuntimeConfig::usage_sample_interval`
+    pub usage_samples: Vec<UsageSample>,
+}
+
+/// One run of the user program, see `TleRerunConfig`.
+#[derive(Debug, Serialize, Clone)]
+pub struct RunAttempt {
+    /// `None` if the run finished within the limits
+    pub verdict: Option<JudgeVerdict>,
+    pub time_usage: Duration,
+    pub real_time_usage: Duration,
+}
+
+impl RunAttempt {
+    pub fn new(verdict: &Option<JudgeVerdict>, raw_info: &SandboxExitInfo) -> Self {
+        Self {
+            verdict: verdict.clone(),
+            time_usage: get_run_time(raw_info),
+            real_time_usage: raw_info.real_time_cost,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Serialize, Clone)]
@@ -20,11 +55,16 @@ pub enum JudgeVerdict {
     Accepted,
     WrongAnswer,
     TimeLimitExceeded,
+    MemoryLimitExceeded,
     IdlenessLimitExceeded,
+    OutputLimitExceeded,
+    RestrictedFunction,
     RuntimeError,
     PartialScore,
     SystemError,
     CompileError,
+    /// Cancelled by the judger before finishing, see `RuntimeConfig::cancellation_token`
+    Cancelled,
 }
 
 impl fmt::Display for JudgeVerdict {
@@ -40,14 +80,53 @@ pub fn get_run_time(raw_info: &SandboxExitInfo) -> Duration {
     utime.add(stime)
 }
 
+/// Peak memory usage in bytes, page cache excluded, see `Rusage::max_rss`
 pub fn get_max_mem(raw_info: &SandboxExitInfo) -> i64 {
     let rusage = &raw_info.resource_usage;
     rusage.max_rss
 }
 
-pub fn check_user_result(config: &JudgeConfig, raw_info: &SandboxExitInfo) -> Option<JudgeVerdict> {
+/// See `ExitReason::get_crash_reason()`.
+pub fn get_crash_reason(
+    verdict: &Option<JudgeVerdict>,
+    raw_info: &SandboxExitInfo,
+    stderr: &Option<String>,
+) -> Option<CrashReason> {
+    match verdict {
+        Some(JudgeVerdict::RuntimeError) => {
+            raw_info.exit_reason.get_crash_reason(stderr.as_deref())
+        }
+        _ => None,
+    }
+}
+
+/// `stderr` is the head of the user program's stderr, see `is_memory_limit_exceeded()`.
+pub fn check_user_result(
+    config: &JudgeConfig,
+    raw_info: &SandboxExitInfo,
+    stderr: Option<&str>,
+) -> Option<JudgeVerdict> {
+    let run_time = get_run_time(raw_info);
+    match &raw_info.exit_reason {
+        ExitReason::KilledBySeccomp(syscall) => {
+            log::debug!("User program called restricted syscall {:?}", syscall);
+            return Some(JudgeVerdict::RestrictedFunction);
+        }
+        ExitReason::KilledByTimeLimit => {
+            log::debug!("User program run time: {:?}", run_time);
+            log::debug!("User program real time: {:?}", raw_info.real_time_cost);
+            // A program spending less than half of the wall time on CPU
+            // is waiting for something (e.g. `sleep()` or reading stdin)
+            if run_time * 2 < raw_info.real_time_cost {
+                return Some(JudgeVerdict::IdlenessLimitExceeded);
+            }
+            return Some(JudgeVerdict::TimeLimitExceeded);
+        }
+        ExitReason::KilledByMemoryLimit => return Some(JudgeVerdict::MemoryLimitExceeded),
+        ExitReason::Exited(_) | ExitReason::Signaled(_, _) => {}
+    }
+
     if let Some(time_limit) = config.runtime.rlimit_configs.get_cpu_limit_duration() {
-        let run_time = get_run_time(raw_info);
         if run_time > time_limit {
             log::debug!("User program run time: {:?}", run_time);
             log::debug!("Time limit: {:?}", time_limit);
@@ -55,21 +134,190 @@ pub fn check_user_result(config: &JudgeConfig, raw_info: &SandboxExitInfo) -> Op
         }
     }
 
-    let exit_status = raw_info.exit_status;
-    log::debug!("User program exit status: {}", exit_status);
-    match exit_status {
-        0 => None,
+    if let Some(memory_limit) = config.runtime.rlimit_configs.get_memory_limit_bytes() {
+        // Memory is limited by `memory.max` rather than `RLIMIT_AS` when cgroup v2 is available
+        let is_address_space_limited = get_cgroup_runs_root().is_none();
+        if is_memory_limit_exceeded(raw_info, stderr, memory_limit, is_address_space_limited) {
+            log::debug!("User program max memory: {}", get_max_mem(raw_info));
+            log::debug!("Memory limit: {} bytes", memory_limit);
+            return Some(JudgeVerdict::MemoryLimitExceeded);
+        }
+    }
+
+    if let Some(output_limit) = config.runtime.rlimit_configs.get_output_limit_bytes() {
+        if is_output_limit_exceeded(config, raw_info, output_limit) {
+            log::debug!("Output limit: {} bytes", output_limit);
+            return Some(JudgeVerdict::OutputLimitExceeded);
+        }
+    }
+
+    log::debug!("User program exit reason: {:?}", raw_info.exit_reason);
+    match raw_info.exit_reason {
+        ExitReason::Exited(0) => None,
         _ => Some(JudgeVerdict::RuntimeError),
     }
 }
 
+/// Whether a TLE from `check_user_result()` is worth re-running:
+/// the program used at most `margin_percent` more CPU time than the limit.
+/// Programs killed by `RLIMIT_CPU` run a second past the limit, so they do not qualify
+/// unless the margin is that wide.
+pub fn is_borderline_tle(
+    config: &JudgeConfig,
+    raw_info: &SandboxExitInfo,
+    margin_percent: u64,
+) -> bool {
+    let time_limit = match config.runtime.rlimit_configs.get_cpu_limit_duration() {
+        Some(time_limit) => time_limit,
+        None => return false,
+    };
+    get_run_time(raw_info) <= time_limit + time_limit * margin_percent as u32 / 100
+}
+
+/// Programs killed by the OOM killer of the cgroup are `ExitReason::KilledByMemoryLimit`.
+/// Besides peak usage reaching the limit, a crash counts as MLE under `RLIMIT_AS`,
+/// which counts mapped rather than resident memory, so an allocation usually fails
+/// before the peak reaches the limit, and the program crashes in whatever way its runtime does:
+/// - at least three quarters of the limit was used, whatever the program printed;
+/// - at least half of the limit was used and the runtime reported the failed allocation
+///   (`CrashReason::BadAlloc`), e.g. when a vector failed to double.
+///
+/// Other crashes are left as runtime errors whatever the usage.
+fn is_memory_limit_exceeded(
+    raw_info: &SandboxExitInfo,
+    stderr: Option<&str>,
+    memory_limit: u64,
+    is_address_space_limited: bool,
+) -> bool {
+    let max_mem = get_max_mem(raw_info) as u64;
+    if max_mem >= memory_limit {
+        return true;
+    }
+    if !is_address_space_limited {
+        return false;
+    }
+
+    match raw_info.exit_reason.get_crash_reason(stderr) {
+        Some(CrashReason::BadAlloc) => max_mem * 2 >= memory_limit,
+        Some(_) => max_mem * 4 >= memory_limit * 3,
+        None => false,
+    }
+}
+
+/// The kernel sends `SIGXFSZ` when a process writes past `RLIMIT_FSIZE`.
+/// If the program ignores the signal, the write fails with `EFBIG` instead,
+/// so the size of the output file is checked as well:
+/// `RLIMIT_FSIZE` is one byte above the limit, see `RlimitConfigs::load()`.
+fn is_output_limit_exceeded(
+    config: &JudgeConfig,
+    raw_info: &SandboxExitInfo,
+    output_limit: u64,
+) -> bool {
+    if let ExitReason::Signaled(libc::SIGXFSZ, _) = raw_info.exit_reason {
+        log::debug!("User program killed by SIGXFSZ");
+        return true;
+    }
+    match fs::metadata(&config.program.output_file_path) {
+        Ok(metadata) => metadata.len() > output_limit,
+        Err(_) => false,
+    }
+}
+
+/// Checkers follow the testlib convention: exiting with 0 accepts and 1 rejects the output.
 pub fn check_checker_result(raw_info: &SandboxExitInfo) -> JudgeVerdict {
     // TODO: return verdict according to the checker output
-    let exit_status = raw_info.exit_status;
-    log::debug!("Checker program exit status: {}", exit_status);
-    match exit_status {
-        0 => JudgeVerdict::Accepted,
-        256 => JudgeVerdict::WrongAnswer,
+    log::debug!("Checker program exit reason: {:?}", raw_info.exit_reason);
+    match raw_info.exit_reason {
+        ExitReason::Exited(0) => JudgeVerdict::Accepted,
+        ExitReason::Exited(1) => JudgeVerdict::WrongAnswer,
         _ => JudgeVerdict::SystemError,
     }
 }
+
+#[test]
+fn test_is_memory_limit_exceeded() {
+    use crate::sandbox::Rusage;
+
+    let raw_info = |exit_reason, max_rss| SandboxExitInfo {
+        exit_status: 0,
+        exit_reason,
+        real_time_cost: Duration::ZERO,
+        resource_usage: Rusage {
+            user_time: Duration::ZERO,
+            system_time: Duration::ZERO,
+            max_rss,
+            page_faults: 0,
+            involuntary_context_switches: 0,
+            voluntary_context_switches: 0,
+        },
+        usage_samples: vec![],
+        learned_syscalls: vec![],
+    };
+    let bad_alloc = Some("terminate called after throwing an instance of 'std::bad_alloc'");
+    let rust_alloc_failure = Some("memory allocation of 1048576 bytes failed");
+    let python_memory_error = Some("Traceback (most recent call last):\nMemoryError");
+    let memory_limit = 1024;
+
+    assert!(is_memory_limit_exceeded(
+        &raw_info(ExitReason::Exited(0), 1024),
+        None,
+        memory_limit,
+        true
+    ));
+    assert!(is_memory_limit_exceeded(
+        &raw_info(ExitReason::Signaled(libc::SIGABRT, false), 600),
+        bad_alloc,
+        memory_limit,
+        true
+    ));
+    assert!(is_memory_limit_exceeded(
+        &raw_info(ExitReason::Signaled(libc::SIGABRT, false), 600),
+        rust_alloc_failure,
+        memory_limit,
+        true
+    ));
+    assert!(is_memory_limit_exceeded(
+        &raw_info(ExitReason::Exited(1), 600),
+        python_memory_error,
+        memory_limit,
+        true
+    ));
+    assert!(!is_memory_limit_exceeded(
+        &raw_info(ExitReason::Signaled(libc::SIGABRT, false), 100),
+        bad_alloc,
+        memory_limit,
+        true
+    ));
+    // A segfault after `malloc()` returned NULL, with nothing printed
+    assert!(is_memory_limit_exceeded(
+        &raw_info(ExitReason::Signaled(libc::SIGSEGV, false), 900),
+        None,
+        memory_limit,
+        true
+    ));
+    assert!(!is_memory_limit_exceeded(
+        &raw_info(ExitReason::Signaled(libc::SIGSEGV, false), 600),
+        None,
+        memory_limit,
+        true
+    ));
+    assert!(!is_memory_limit_exceeded(
+        &raw_info(ExitReason::Exited(0), 900),
+        None,
+        memory_limit,
+        true
+    ));
+    // Allocations do not fail under `memory.max`, the OOM killer is reported instead
+    assert!(!is_memory_limit_exceeded(
+        &raw_info(ExitReason::Signaled(libc::SIGSEGV, false), 900),
+        None,
+        memory_limit,
+        false
+    ));
+    assert!(!is_memory_limit_exceeded(
+        &raw_info(ExitReason::Signaled(libc::SIGABRT, false), 600),
+        bad_alloc,
+        memory_limit,
+        false
+    ));
+}
@@ -1,3 +1,5 @@
+/// cgroup v2 resource controller, used by the sandbox when available
+pub mod cgroup;
 /// Helper for compiling source into an executable
 pub mod compiler;
 pub mod error;
@@ -8,7 +10,4 @@ pub mod package;
 pub mod run;
 pub mod utils;
 
-/// Currently an unused module
-mod cgroup;
-
 pub mod sandbox;
@@ -6,6 +6,7 @@ use anyhow::anyhow;
 use crate::{
     error::JudgeCoreError,
     judge::{CheckerConfig, TestdataConfig},
+    run::executor::ExecutorConfigs,
     sandbox::{RlimitConfigs, DEFAULT_RLIMIT_CONFIGS},
 };
 
@@ -42,6 +43,10 @@ impl PackageAgent for ICPCPackageAgent {
         let mut cpu_limit = DEFAULT_RLIMIT_CONFIGS.cpu_limit;
         let nproc_limit = DEFAULT_RLIMIT_CONFIGS.nproc_limit;
         let mut fsize_limit = DEFAULT_RLIMIT_CONFIGS.fsize_limit;
+        let nofile_limit = DEFAULT_RLIMIT_CONFIGS.nofile_limit;
+        let core_limit = DEFAULT_RLIMIT_CONFIGS.core_limit;
+        let mut real_time_limit = DEFAULT_RLIMIT_CONFIGS.real_time_limit;
+        let mut max_threads = DEFAULT_RLIMIT_CONFIGS.max_threads;
         log::debug!("reading rlimit from {:?}", self.package_path);
 
         let time_limit_path = self.package_path.join(".timelimit");
@@ -49,6 +54,8 @@ impl PackageAgent for ICPCPackageAgent {
             let content = fs::read_to_string(time_limit_path).unwrap();
             let time_limit = content.trim().parse::<u64>().unwrap();
             cpu_limit = Some((time_limit, time_limit));
+            // leave enough room for IO and context switches before killing by wall clock
+            real_time_limit = Some(time_limit * 3);
         } else {
             log::info!("timelimit file not found, use default config");
         }
@@ -67,9 +74,14 @@ impl PackageAgent for ICPCPackageAgent {
                 }
                 if let Some(output) = limits.get("output") {
                     if let Some(output_u64) = output.as_u64() {
-                        fsize_limit = Some((output_u64, output_u64));
+                        // the unit of output limit is MiB, same as memory limit
+                        fsize_limit = Some((output_u64 * 1024 * 1024, output_u64 * 1024 * 1024));
                     }
                 }
+                // Not a part of the ICPC format, for problems allowing parallelism
+                if let Some(threads) = limits.get("threads") {
+                    max_threads = threads.as_u64();
+                }
             }
         }
 
@@ -79,6 +91,11 @@ impl PackageAgent for ICPCPackageAgent {
             cpu_limit,
             nproc_limit,
             fsize_limit,
+            nofile_limit,
+            core_limit,
+            real_time_limit,
+            max_threads,
+            cpu_pinning: DEFAULT_RLIMIT_CONFIGS.cpu_pinning,
         })
     }
 
@@ -114,6 +131,39 @@ impl PackageAgent for ICPCPackageAgent {
             output_file_path: checker_output_path,
         })
     }
+
+    /// Read from the `executor` section of `problem.yaml`, which is not a part of the ICPC format.
+    fn get_executor_configs(&self) -> Result<ExecutorConfigs, JudgeCoreError> {
+        let yaml_path = self.package_path.join("problem.yaml");
+        if !yaml_path.exists() {
+            return Ok(ExecutorConfigs::default());
+        }
+        let content = fs::read_to_string(yaml_path)?;
+        let problem_meta = serde_yaml::from_str::<serde_yaml::Value>(&content)
+            .map_err(|e| JudgeCoreError::AnyhowError(e.into()))?;
+        match problem_meta.get("executor") {
+            Some(executor) => serde_yaml::from_value(executor.clone())
+                .map_err(|e| JudgeCoreError::AnyhowError(e.into())),
+            None => Ok(ExecutorConfigs::default()),
+        }
+    }
+
+    /// Read from the `seccomp_profile` key of `problem.yaml`, which is not a part of the ICPC format.
+    fn get_seccomp_profile_name(&self) -> Result<Option<String>, JudgeCoreError> {
+        let yaml_path = self.package_path.join("problem.yaml");
+        if !yaml_path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(yaml_path)?;
+        let problem_meta = serde_yaml::from_str::<serde_yaml::Value>(&content)
+            .map_err(|e| JudgeCoreError::AnyhowError(e.into()))?;
+        match problem_meta.get("seccomp_profile") {
+            Some(name) => serde_yaml::from_value(name.clone())
+                .map(Some)
+                .map_err(|e| JudgeCoreError::AnyhowError(e.into())),
+            None => Ok(None),
+        }
+    }
 }
 
 fn copy_testdata_recursively(
@@ -150,3 +200,31 @@ fn copy_testdata_recursively(
 
     Ok(testdata_configs)
 }
+
+#[test]
+fn test_get_seccomp_profile_name() {
+    let package_path = std::env::temp_dir().join("judge-core-test-icpc-package");
+    fs::create_dir_all(&package_path).unwrap();
+    let package_agent = ICPCPackageAgent::init(package_path.clone()).unwrap();
+
+    fs::write(package_path.join("problem.yaml"), "name: Hello world\n").unwrap();
+    assert_eq!(package_agent.get_seccomp_profile_name().unwrap(), None);
+
+    fs::write(
+        package_path.join("problem.yaml"),
+        "name: Hello world\nseccomp_profile: python\n",
+    )
+    .unwrap();
+    assert_eq!(
+        package_agent.get_seccomp_profile_name().unwrap(),
+        Some("python".to_string())
+    );
+
+    fs::write(
+        package_path.join("problem.yaml"),
+        "seccomp_profile: [cpp]\n",
+    )
+    .unwrap();
+    assert!(package_agent.get_seccomp_profile_name().is_err());
+    fs::remove_dir_all(&package_path).unwrap();
+}
@@ -7,6 +7,7 @@ use serde_derive::{Deserialize, Serialize};
 use crate::{
     error::JudgeCoreError,
     judge::{CheckerConfig, TestdataConfig},
+    run::executor::ExecutorConfigs,
     sandbox::RlimitConfigs,
 };
 
@@ -20,6 +21,11 @@ pub trait PackageAgent {
     fn get_rlimit_configs(&self) -> Result<RlimitConfigs, JudgeCoreError>;
     fn load_testdata(&self, dest: PathBuf) -> Result<Vec<TestdataConfig>, JudgeCoreError>;
     fn load_checker(&self, dest: PathBuf) -> Result<CheckerConfig, JudgeCoreError>;
+    /// Applied to the user program on top of the language defaults
+    fn get_executor_configs(&self) -> Result<ExecutorConfigs, JudgeCoreError>;
+    /// Name of the seccomp profile required by the problem (see `SeccompProfiles`),
+    /// `None` leaves it to the language
+    fn get_seccomp_profile_name(&self) -> Result<Option<String>, JudgeCoreError>;
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
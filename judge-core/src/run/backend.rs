@@ -0,0 +1,141 @@
+use crate::error::JudgeCoreError;
+use crate::sandbox::cancellation::CancellationToken;
+use crate::sandbox::jail::JailConfigs;
+use crate::sandbox::namespace::NamespaceConfigs;
+use crate::sandbox::seccomp::SeccompProfile;
+use crate::sandbox::usage::UsageSample;
+use crate::sandbox::{RlimitConfigs, SandboxExitInfo};
+use anyhow::anyhow;
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use std::os::fd::BorrowedFd;
+use std::os::unix::io::RawFd;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[cfg(feature = "async")]
+use std::{future::Future, pin::Pin};
+
+use super::executor::Executor;
+use super::sandbox::ExecutorSandbox;
+
+/// What a `SandboxBackend` is asked to run, and how.
+/// Backends may ignore the settings they have no counterpart of.
+#[derive(Debug, Clone)]
+pub struct SandboxSpec {
+    pub executor: Executor,
+    pub rlimit_configs: RlimitConfigs,
+    /// stdin is closed unless redirected
+    pub input_redirect: Option<RawFd>,
+    /// stdout is closed unless redirected
+    pub output_redirect: Option<RawFd>,
+    /// stderr goes to `/dev/null` unless redirected
+    pub error_redirect: Option<RawFd>,
+    /// `None` lets every syscall through
+    pub seccomp_profile: Option<SeccompProfile>,
+    pub namespace_configs: NamespaceConfigs,
+    pub jail_configs: Option<JailConfigs>,
+    pub deterministic: bool,
+    pub usage_sample_interval: Option<Duration>,
+    pub cancellation_token: Option<CancellationToken>,
+}
+
+impl SandboxSpec {
+    /// Nothing redirected or restricted besides the rlimits.
+    pub fn new(executor: Executor, rlimit_configs: RlimitConfigs) -> Self {
+        Self {
+            executor,
+            rlimit_configs,
+            input_redirect: None,
+            output_redirect: None,
+            error_redirect: None,
+            seccomp_profile: None,
+            namespace_configs: NamespaceConfigs::default(),
+            jail_configs: None,
+            deterministic: false,
+            usage_sample_interval: None,
+            cancellation_token: None,
+        }
+    }
+}
+
+/// Runs one sandboxed process for judging, see `SandboxBackendKind` for the available ones.
+///
+/// Besides blocking in `wait()`, a backend can be watched in an event loop:
+/// call `poll_exit()` whenever `get_event_fd()` is readable until it returns `true`,
+/// then `wait()` returns without blocking for long.
+pub trait SandboxBackend: Send {
+    /// Start the process with every setting of the `SandboxSpec`, returning its pid.
+    /// Fails with `JudgeCoreError::SpawnFailed` when the process fails before running the program.
+    fn spawn(&mut self) -> Result<i32, JudgeCoreError>;
+
+    /// Block until the process exits or is killed (e.g. for the real time limit), then reap it.
+    /// Fails with `JudgeCoreError::Cancelled` when the cancellation token is cancelled.
+    fn wait(&self) -> Result<SandboxExitInfo, JudgeCoreError>;
+
+    /// The async counterpart of `wait()`, which does not block the tokio runtime.
+    #[cfg(feature = "async")]
+    fn wait_async(
+        &mut self,
+    ) -> Pin<Box<dyn Future<Output = Result<SandboxExitInfo, JudgeCoreError>> + Send + '_>>;
+
+    /// Kill the whole process tree, which is left for `wait()` to reap.
+    fn kill(&self) -> Result<(), JudgeCoreError>;
+
+    /// The current usage of the running process tree, `None` if unavailable.
+    fn sample_usage(&self) -> Option<UsageSample>;
+
+    /// Readable whenever `poll_exit()` has something to do, available once spawned.
+    /// It stays the same fd until the backend is dropped.
+    fn get_event_fd(&self) -> Result<BorrowedFd<'_>, JudgeCoreError>;
+
+    /// Handle what happened to the process without blocking, e.g. enforcing the limits
+    /// which are not left to the kernel. Returns `true` once the process has exited or is killed.
+    fn poll_exit(&self) -> Result<bool, JudgeCoreError>;
+}
+
+/// The backends to choose from per deployment.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SandboxBackendKind {
+    /// `ExecutorSandbox`, a forked process restricted by rlimits, seccomp, namespaces and cgroups
+    #[default]
+    Native,
+}
+
+impl SandboxBackendKind {
+    /// The sandbox is not spawned yet.
+    pub fn create(&self, spec: SandboxSpec) -> Result<Box<dyn SandboxBackend>, JudgeCoreError> {
+        match self {
+            Self::Native => Ok(Box::new(ExecutorSandbox::from_spec(spec)?)),
+        }
+    }
+}
+
+impl FromStr for SandboxBackendKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "native" => Ok(Self::Native),
+            _ => Err(anyhow!("Invalid sandbox backend: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for SandboxBackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Native => write!(f, "native"),
+        }
+    }
+}
+
+#[test]
+fn test_parse_sandbox_backend_kind() {
+    assert_eq!(
+        "native".parse::<SandboxBackendKind>().unwrap(),
+        SandboxBackendKind::Native
+    );
+    assert_eq!(SandboxBackendKind::Native.to_string(), "native");
+    assert!("wasm".parse::<SandboxBackendKind>().is_err());
+}
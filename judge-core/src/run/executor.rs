@@ -1,14 +1,57 @@
 use crate::error::{path_not_exist, JudgeCoreError};
 use crate::{compiler::Language, utils::get_pathbuf_str};
 use nix::unistd::execve;
-use serde_derive::Serialize;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::{convert::Infallible, ffi::CString, path::PathBuf};
 
+/// Enough for finding interpreters, the jail only provides the runtime paths anyway
+pub static DEFAULT_PATH_ENV: &str = "/usr/local/bin:/usr/bin:/bin";
+pub static DEFAULT_LANG_ENV: &str = "C.UTF-8";
+
+/// How a program is executed, set by the language and the problem package.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecutorConfigs {
+    /// Added to the environment, overriding the variables with the same names
+    #[serde(default)]
+    pub envs: BTreeMap<String, String>,
+    /// The working directory as seen by the program (inside the jail if any)
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
+    /// Appended to the arguments
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+impl ExecutorConfigs {
+    /// The same for every run, so that programs do not depend on the judger's environment.
+    /// `HOME` depends on where the run is, see `Executor::set_scratch_dir()`.
+    pub fn default_for_language(language: Language) -> Self {
+        let mut envs = BTreeMap::new();
+        envs.insert("PATH".to_string(), DEFAULT_PATH_ENV.to_string());
+        envs.insert("LANG".to_string(), DEFAULT_LANG_ENV.to_string());
+        if language == Language::Python {
+            // The jail is read-only, and the scratch dir is not worth filling with caches
+            envs.insert("PYTHONDONTWRITEBYTECODE".to_string(), "1".to_string());
+            envs.insert("PYTHONIOENCODING".to_string(), "utf-8".to_string());
+        }
+        Self {
+            envs,
+            cwd: None,
+            args: vec![],
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct Executor {
     pub language: Language,
     pub path: PathBuf,
+    /// Passed after the executable (or the script for interpreted languages)
     pub additional_args: Vec<String>,
+    pub envs: BTreeMap<String, String>,
+    /// Changed into by the sandbox before executing, `None` keeps the working directory
+    pub cwd: Option<PathBuf>,
 }
 
 // Do not do logging or some other additional things which may use system calls
@@ -19,10 +62,13 @@ impl Executor {
             return Err(path_not_exist(&path));
         }
 
+        let configs = ExecutorConfigs::default_for_language(language);
         Ok(Self {
             language,
             path,
-            additional_args: vec![],
+            additional_args: configs.args,
+            envs: configs.envs,
+            cwd: configs.cwd,
         })
     }
 
@@ -30,6 +76,36 @@ impl Executor {
         self.additional_args = args;
     }
 
+    pub fn set_env(&mut self, key: String, value: String) {
+        self.envs.insert(key, value);
+    }
+
+    pub fn set_cwd(&mut self, cwd: Option<PathBuf>) {
+        self.cwd = cwd;
+    }
+
+    /// Use `scratch_dir` as `HOME` and the working directory:
+    /// `JAIL_SCRATCH_PATH` in the jail, or a directory of the run's own without one,
+    /// never the `/tmp` shared with the host.
+    pub fn set_scratch_dir(&mut self, scratch_dir: PathBuf) {
+        self.set_env(
+            "HOME".to_string(),
+            scratch_dir.to_string_lossy().to_string(),
+        );
+        self.set_cwd(Some(scratch_dir));
+    }
+
+    /// Configs applied later take precedence, except that arguments are appended.
+    pub fn apply_configs(&mut self, configs: &ExecutorConfigs) {
+        for (key, value) in configs.envs.iter() {
+            self.set_env(key.clone(), value.clone());
+        }
+        if configs.cwd.is_some() {
+            self.set_cwd(configs.cwd.clone());
+        }
+        self.additional_args.extend(configs.args.iter().cloned());
+    }
+
     pub fn exec(&self) -> Result<Infallible, JudgeCoreError> {
         let (command, args) = self.build_execute_cmd_with_args()?;
         let mut final_args = args;
@@ -38,10 +114,15 @@ impl Executor {
             .iter()
             .map(|s| CString::new(s.as_bytes()))
             .collect::<Result<Vec<_>, _>>()?;
+        let c_envs = self
+            .envs
+            .iter()
+            .map(|(key, value)| CString::new(format!("{}={}", key, value)))
+            .collect::<Result<Vec<_>, _>>()?;
         Ok(execve(
             &CString::new(command)?,
             c_args.as_slice(),
-            &[CString::new("")?],
+            c_envs.as_slice(),
         )?)
     }
 
@@ -54,8 +135,8 @@ impl Executor {
         }
         .to_owned();
         let args = match self.language {
-            Language::Rust => vec![],
-            Language::Cpp => vec![],
+            Language::Rust => vec![path_string],
+            Language::Cpp => vec![path_string],
             Language::Python => {
                 vec!["python3".to_string(), path_string]
             }
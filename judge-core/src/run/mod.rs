@@ -1,3 +1,4 @@
+/// Extension point for how sandboxed processes are run
+pub mod backend;
 pub mod executor;
-pub mod process_listener;
 pub mod sandbox;
@@ -1,22 +1,34 @@
 use crate::error::JudgeCoreError;
+use crate::sandbox::cancellation::CancellationToken;
+use crate::sandbox::jail::JailConfigs;
+use crate::sandbox::namespace::NamespaceConfigs;
+use crate::sandbox::seccomp::SeccompProfile;
+use crate::sandbox::usage::UsageSample;
 use crate::sandbox::RlimitConfigs;
 use crate::sandbox::Sandbox;
 use crate::sandbox::SandboxExitInfo;
-use libc::rusage;
-use libseccomp::{ScmpAction, ScmpFilterContext, ScmpSyscall};
+use nix::errno::Errno;
+use nix::fcntl::{open, OFlag};
+use nix::sys::stat::Mode;
 use nix::unistd::close;
 use nix::unistd::dup2;
-use serde_derive::{Deserialize, Serialize};
 use std::io;
+use std::os::fd::BorrowedFd;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::time::Duration;
+#[cfg(feature = "async")]
+use std::{future::Future, pin::Pin};
 
+use super::backend::{SandboxBackend, SandboxSpec};
 use super::executor::Executor;
 
+pub use crate::sandbox::Rusage;
+
 pub struct ExecutorSandbox {
     executor: Executor,
     input_redirect: Option<RawFd>,
     output_redirect: Option<RawFd>,
+    error_redirect: Option<RawFd>,
 
     pub sandbox: Sandbox,
 }
@@ -27,69 +39,154 @@ impl ExecutorSandbox {
         rlimit_configs: RlimitConfigs,
         input_redirect: Option<RawFd>,
         output_redirect: Option<RawFd>,
-        restricted: bool,
+        seccomp_profile: Option<&SeccompProfile>,
     ) -> Result<Self, JudgeCoreError> {
-        log::debug!("Create sandbox with restricted={}", restricted);
-        let mut scmp_filter = match restricted {
-            true => ScmpFilterContext::new_filter(ScmpAction::KillProcess)?,
-            false => ScmpFilterContext::new_filter(ScmpAction::Allow)?,
-        };
-        if restricted {
-            let white_list = DEFAULT_SCMP_WHITELIST;
-            for s in white_list.iter() {
-                let syscall = ScmpSyscall::from_name(s)?;
-                scmp_filter.add_rule_exact(ScmpAction::Allow, syscall)?;
+        log::debug!("Create sandbox with seccomp profile {:?}", seccomp_profile);
+        let scmp_filter = match seccomp_profile {
+            Some(seccomp_profile) if rlimit_configs.allows_threads() => {
+                let mut seccomp_profile = seccomp_profile.clone();
+                seccomp_profile.allow_threads();
+                Some(seccomp_profile.build_filter()?)
             }
-        }
+            Some(seccomp_profile) => Some(seccomp_profile.build_filter()?),
+            None => None,
+        };
 
-        let sandbox = Sandbox::new(Some(rlimit_configs), Some(scmp_filter))?;
+        let mut sandbox = Sandbox::new(Some(rlimit_configs), scmp_filter)?;
+        sandbox.set_cwd(executor.cwd.clone());
         Ok(Self {
             executor,
             input_redirect,
             output_redirect,
+            error_redirect: None,
             sandbox,
         })
     }
 
+    /// The native `SandboxBackend`, with every setting of `spec` applied.
+    pub fn from_spec(spec: SandboxSpec) -> Result<Self, JudgeCoreError> {
+        let mut sandbox = Self::new(
+            spec.executor,
+            spec.rlimit_configs,
+            spec.input_redirect,
+            spec.output_redirect,
+            spec.seccomp_profile.as_ref(),
+        )?;
+        if let Some(error_redirect) = spec.error_redirect {
+            sandbox.set_error_redirect(error_redirect);
+        }
+        sandbox.set_namespace_configs(spec.namespace_configs);
+        sandbox.set_jail_configs(spec.jail_configs)?;
+        sandbox.set_deterministic(spec.deterministic);
+        sandbox.set_usage_sample_interval(spec.usage_sample_interval);
+        sandbox.set_cancellation_token(spec.cancellation_token);
+        Ok(sandbox)
+    }
+
+    /// stderr goes to `/dev/null` unless redirected.
+    pub fn set_error_redirect(&mut self, error_redirect: RawFd) {
+        self.error_redirect = Some(error_redirect);
+    }
+
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.sandbox.set_deterministic(deterministic);
+    }
+
+    pub fn set_cancellation_token(&mut self, cancellation_token: Option<CancellationToken>) {
+        self.sandbox.set_cancellation_token(cancellation_token);
+    }
+
+    /// See `Sandbox::enable_syscall_learning()`.
+    pub fn enable_syscall_learning(&mut self) -> Result<(), JudgeCoreError> {
+        self.sandbox.enable_syscall_learning()
+    }
+
+    pub fn set_usage_sample_interval(&mut self, interval: Option<Duration>) {
+        self.sandbox.set_usage_sample_interval(interval);
+    }
+
+    pub fn set_namespace_configs(&mut self, namespace_configs: NamespaceConfigs) {
+        self.sandbox.set_namespace_configs(namespace_configs);
+    }
+
+    /// The executable is bound into the jail, with its path made absolute.
+    pub fn set_jail_configs(
+        &mut self,
+        jail_configs: Option<JailConfigs>,
+    ) -> Result<(), JudgeCoreError> {
+        let jail_configs = match jail_configs {
+            Some(mut jail_configs) => {
+                self.executor.path = self.executor.path.canonicalize()?;
+                jail_configs.add_readonly_bind(self.executor.path.clone());
+                Some(jail_configs)
+            }
+            None => None,
+        };
+        self.sandbox.set_jail_configs(jail_configs);
+        Ok(())
+    }
+
     pub fn wait(&self) -> Result<SandboxExitInfo, JudgeCoreError> {
         self.sandbox.wait()
     }
 
-    /// WARNING:   
-    /// Unsafe to use `println!()` (or `unwrap()`) in child process.
-    /// See more in `fork()` document.
+    /// See `Sandbox::wait_async()`.
+    #[cfg(feature = "async")]
+    pub async fn wait_async(&mut self) -> Result<SandboxExitInfo, JudgeCoreError> {
+        self.sandbox.wait_async().await
+    }
+
+    /// Spawn the process, the returned future resolves when it exits
+    /// without blocking the tokio runtime.
+    #[cfg(feature = "async")]
+    pub async fn spawn_async(&mut self) -> Result<SandboxExitInfo, JudgeCoreError> {
+        self.spawn()?;
+        self.wait_async().await
+    }
+
+    /// See `Sandbox::spawn()`, failures of redirecting or executing are reported
+    /// as `JudgeCoreError::SpawnFailed` as well.
     pub fn spawn(&mut self) -> Result<i32, JudgeCoreError> {
         let before_limit = {
             let input_redirect = self.input_redirect;
             let output_redirect = self.output_redirect;
-            move || {
+            let error_redirect = self.error_redirect;
+            move || -> Result<(), Errno> {
                 let stderr_raw_fd = io::stderr().as_raw_fd();
-                if let Some(output_redirect) = output_redirect {
-                    dup2(output_redirect, stderr_raw_fd).expect("Failed to dup2 stderr");
-                } else {
-                    close(io::stdin().as_raw_fd()).expect("Failed to close stdin");
+                match error_redirect {
+                    Some(error_redirect) => {
+                        dup2(error_redirect, stderr_raw_fd)?;
+                    }
+                    None => {
+                        // Not closed, or the next opened file would take its place
+                        let dev_null = open("/dev/null", OFlag::O_WRONLY, Mode::empty())?;
+                        dup2(dev_null, stderr_raw_fd)?;
+                        close(dev_null)?;
+                    }
                 }
 
                 let stdin_raw_fd = io::stdin().as_raw_fd();
                 let stdout_raw_fd = io::stdout().as_raw_fd();
                 if let Some(input_redirect) = input_redirect {
-                    dup2(input_redirect, stdin_raw_fd).expect("Failed to dup2 stdin");
+                    dup2(input_redirect, stdin_raw_fd)?;
                 } else {
-                    close(stdin_raw_fd).expect("Failed to close stdin");
+                    close(stdin_raw_fd)?;
                 }
 
                 if let Some(output_redirect) = output_redirect {
-                    dup2(output_redirect, stdout_raw_fd).expect("Failed to dup2 stdout");
+                    dup2(output_redirect, stdout_raw_fd)?;
                 } else {
-                    close(stdout_raw_fd).expect("Failed to close stdout");
+                    close(stdout_raw_fd)?;
                 }
+                Ok(())
             }
         };
 
         let after_limit = {
             let executor = self.executor.clone();
             move || {
-                executor.exec().expect("Failed to exec");
+                // Reported by the sandbox when it returns
+                let _ = executor.exec();
             }
         };
 
@@ -97,75 +194,35 @@ impl ExecutorSandbox {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Rusage {
-    pub user_time: Duration,
-    pub system_time: Duration,
-    pub max_rss: i64,
-    pub page_faults: i64,
-    pub involuntary_context_switches: i64,
-    pub voluntary_context_switches: i64,
-}
+impl SandboxBackend for ExecutorSandbox {
+    fn spawn(&mut self) -> Result<i32, JudgeCoreError> {
+        ExecutorSandbox::spawn(self)
+    }
 
-impl From<rusage> for Rusage {
-    fn from(rusage: rusage) -> Self {
-        Self {
-            user_time: Duration::new(
-                rusage.ru_utime.tv_sec as u64,
-                rusage.ru_utime.tv_usec as u32 * 1000,
-            ),
-            system_time: Duration::new(
-                rusage.ru_stime.tv_sec as u64,
-                rusage.ru_stime.tv_usec as u32 * 1000,
-            ),
-            max_rss: rusage.ru_maxrss,
-            page_faults: rusage.ru_majflt,
-            involuntary_context_switches: rusage.ru_nivcsw,
-            voluntary_context_switches: rusage.ru_nvcsw,
-        }
+    fn wait(&self) -> Result<SandboxExitInfo, JudgeCoreError> {
+        self.sandbox.wait()
+    }
+
+    #[cfg(feature = "async")]
+    fn wait_async(
+        &mut self,
+    ) -> Pin<Box<dyn Future<Output = Result<SandboxExitInfo, JudgeCoreError>> + Send + '_>> {
+        Box::pin(self.sandbox.wait_async())
     }
-}
 
-const DEFAULT_SCMP_WHITELIST: [&str; 41] = [
-    "read",
-    "fstat",
-    "mmap",
-    "mprotect",
-    "munmap",
-    "uname",
-    "arch_prctl",
-    "brk",
-    "access",
-    "exit_group",
-    "close",
-    "readlink",
-    "sysinfo",
-    "write",
-    "writev",
-    "lseek",
-    "clock_gettime",
-    "pread64",
-    "execve",
-    "newfstatat",
-    "getrandom",
-    "set_tid_address",
-    "set_robust_list",
-    "rseq",
-    "prlimit64",
-    "futex",
-    "openat",
-    "getcwd",
-    "gettid",
-    "ioctl",
-    "getdents64",
-    "rt_sigaction",
-    "getegid",
-    "geteuid",
-    "getgid",
-    "getuid",
-    "fcntl",
-    "getpid",
-    "socket",
-    "dup",
-    "connect",
-];
+    fn kill(&self) -> Result<(), JudgeCoreError> {
+        self.sandbox.kill()
+    }
+
+    fn sample_usage(&self) -> Option<UsageSample> {
+        self.sandbox.sample_usage()
+    }
+
+    fn get_event_fd(&self) -> Result<BorrowedFd<'_>, JudgeCoreError> {
+        self.sandbox.get_event_fd()
+    }
+
+    fn poll_exit(&self) -> Result<bool, JudgeCoreError> {
+        self.sandbox.supervise()
+    }
+}
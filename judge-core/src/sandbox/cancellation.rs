@@ -0,0 +1,60 @@
+use crate::error::JudgeCoreError;
+use nix::sys::eventfd::{EfdFlags, EventFd};
+use std::os::fd::{AsFd, BorrowedFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A handle for cancelling sandboxes from another thread, see `Sandbox::set_cancellation_token()`.
+/// Clones share the same state, and a cancelled token stays cancelled.
+#[derive(Debug, Clone)]
+pub struct CancellationToken {
+    inner: Arc<CancellationInner>,
+}
+
+#[derive(Debug)]
+struct CancellationInner {
+    cancelled: AtomicBool,
+    // Readable once cancelled, for waking up the supervisors polling it
+    event_fd: EventFd,
+}
+
+impl CancellationToken {
+    pub fn new() -> Result<Self, JudgeCoreError> {
+        let event_fd = EventFd::from_value_and_flags(0, EfdFlags::EFD_CLOEXEC)?;
+        Ok(Self {
+            inner: Arc::new(CancellationInner {
+                cancelled: AtomicBool::new(false),
+                event_fd,
+            }),
+        })
+    }
+
+    /// Kill the process trees of the sandboxes using this token,
+    /// their `wait()` returns `JudgeCoreError::Cancelled`.
+    pub fn cancel(&self) {
+        if !self.inner.cancelled.swap(true, Ordering::SeqCst) {
+            // Never read, so the fd stays readable for every supervisor
+            if let Err(e) = self.inner.event_fd.write(1) {
+                log::warn!("Failed to notify cancellation: {:?}", e);
+            }
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn as_fd(&self) -> BorrowedFd<'_> {
+        self.inner.event_fd.as_fd()
+    }
+}
+
+#[test]
+fn test_cancellation_token() {
+    let token = CancellationToken::new().unwrap();
+    let cloned = token.clone();
+    assert!(!cloned.is_cancelled());
+    token.cancel();
+    token.cancel();
+    assert!(cloned.is_cancelled());
+}
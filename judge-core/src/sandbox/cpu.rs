@@ -0,0 +1,146 @@
+use anyhow::anyhow;
+use nix::errno::Errno;
+use nix::sched::{sched_getaffinity, CpuSet};
+use nix::unistd::Pid;
+use serde_derive::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use super::lease::{Lease, LeasePool};
+use crate::error::JudgeCoreError;
+
+static CPU_POOL: OnceLock<CpuPool> = OnceLock::new();
+
+/// Replace the default pool with `cores`.
+/// Should be called before any sandbox is created.
+pub fn init_cpu_pool(cores: Vec<usize>) -> Result<(), JudgeCoreError> {
+    if cores.is_empty() {
+        return Err(JudgeCoreError::AnyhowError(anyhow!("CPU pool is empty")));
+    }
+    CPU_POOL
+        .set(CpuPool::new("CPU pool", cores))
+        .map_err(|_| JudgeCoreError::AnyhowError(anyhow!("CPU pool is already in use")))
+}
+
+/// Get the pool set by `init_cpu_pool()`, or a pool of the cores the judger can run on,
+/// except the first one which is left for the judger and the system when there are others.
+pub fn get_cpu_pool() -> Result<&'static CpuPool, JudgeCoreError> {
+    if let Some(pool) = CPU_POOL.get() {
+        return Ok(pool);
+    }
+    let cpu_set = sched_getaffinity(Pid::from_raw(0))?;
+    let mut cores = (0..CpuSet::count())
+        .filter(|core| cpu_set.is_set(*core).unwrap_or(false))
+        .collect::<Vec<_>>();
+    if cores.len() > 1 {
+        cores.remove(0);
+    }
+    log::info!("Using cores {:?} for pinning", cores);
+    Ok(CPU_POOL.get_or_init(|| CpuPool::new("CPU pool", cores)))
+}
+
+/// Parse a core list like `2,3,5-7`.
+pub fn parse_core_list(list: &str) -> Result<Vec<usize>, JudgeCoreError> {
+    let parse = |core: &str| {
+        core.trim()
+            .parse::<usize>()
+            .map_err(|e| JudgeCoreError::AnyhowError(e.into()))
+    };
+    let mut cores = vec![];
+    for part in list.split(',').filter(|part| !part.trim().is_empty()) {
+        match part.split_once('-') {
+            Some((first, last)) => cores.extend(parse(first)?..=parse(last)?),
+            None => cores.push(parse(part)?),
+        }
+    }
+    Ok(cores)
+}
+
+/// Scheduling policy of a pinned run, the real-time ones require root.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchedPolicy {
+    /// `SCHED_OTHER`
+    Other,
+    /// `SCHED_BATCH`, preempted less often by the other normal processes
+    Batch,
+    /// `SCHED_FIFO` with the priority, nothing but higher priority tasks preempts it
+    Fifo(i32),
+    /// `SCHED_RR` with the priority
+    RoundRobin(i32),
+}
+
+impl SchedPolicy {
+    /// Set the policy of the calling process, inherited by its children and threads.
+    pub(crate) fn apply(&self) -> Result<(), Errno> {
+        let (policy, priority) = match *self {
+            Self::Other => (libc::SCHED_OTHER, 0),
+            Self::Batch => (libc::SCHED_BATCH, 0),
+            Self::Fifo(priority) => (libc::SCHED_FIFO, priority),
+            Self::RoundRobin(priority) => (libc::SCHED_RR, priority),
+        };
+        let param = libc::sched_param {
+            sched_priority: priority,
+        };
+        if unsafe { libc::sched_setscheduler(0, policy, &param) } != 0 {
+            return Err(Errno::last());
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SchedPolicy {
+    type Err = anyhow::Error;
+
+    /// `other`, `batch`, `fifo:<priority>` or `rr:<priority>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, priority) = match s.split_once(':') {
+            Some((name, priority)) => (name, Some(priority.parse::<i32>()?)),
+            None => (s, None),
+        };
+        match (name, priority) {
+            ("other", None) => Ok(Self::Other),
+            ("batch", None) => Ok(Self::Batch),
+            ("fifo", Some(priority)) => Ok(Self::Fifo(priority)),
+            ("rr", Some(priority)) => Ok(Self::RoundRobin(priority)),
+            _ => Err(anyhow!("Invalid scheduling policy: {}", s)),
+        }
+    }
+}
+
+/// Hands out a distinct core to each pinned run, see `LeasePool`.
+pub type CpuPool = LeasePool<usize>;
+
+/// A core of the `CpuPool`.
+pub type CpuLease<'a> = Lease<'a, usize>;
+
+impl CpuLease<'_> {
+    /// Pin the calling process to the leased core, inherited by its children and threads.
+    pub(crate) fn pin(&self) -> Result<(), Errno> {
+        let mut cpu_set = CpuSet::new();
+        cpu_set.set(**self)?;
+        nix::sched::sched_setaffinity(Pid::from_raw(0), &cpu_set)
+    }
+}
+
+#[test]
+fn test_cpu_pool() {
+    let pool = CpuPool::new("CPU pool", vec![2, 3]);
+    let first = pool.lease();
+    let second = pool.lease();
+    assert_eq!((*first, *second), (2, 3));
+    drop(first);
+    assert_eq!(*pool.lease(), 2);
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(parse_core_list("2,3,5-7").unwrap(), vec![2, 3, 5, 6, 7]);
+    assert!(parse_core_list("a").is_err());
+    assert_eq!("batch".parse::<SchedPolicy>().unwrap(), SchedPolicy::Batch);
+    assert_eq!(
+        "fifo:10".parse::<SchedPolicy>().unwrap(),
+        SchedPolicy::Fifo(10)
+    );
+    assert!("fifo".parse::<SchedPolicy>().is_err());
+}
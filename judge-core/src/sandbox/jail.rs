@@ -0,0 +1,301 @@
+use nix::errno::Errno;
+use nix::mount::{mount, umount2, MntFlags, MsFlags};
+use nix::sys::statvfs::{statvfs, FsFlags};
+use nix::unistd::{chdir, pivot_root};
+use serde_derive::{Deserialize, Serialize};
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use crate::error::JudgeCoreError;
+
+/// Name of the directory (under the system temp dir) where the jail root is built.
+/// Every run mounts its own tmpfs on it inside a private mount namespace,
+/// so the directory can be shared.
+pub static JAIL_ROOT_DIRNAME: &str = "judger-jail";
+/// The writable scratch dir inside the jail
+pub static JAIL_SCRATCH_PATH: &str = "/tmp";
+
+/// Read-only paths which are enough for running compiled programs and common interpreters
+pub static DEFAULT_RUNTIME_BINDS: [&str; 5] =
+    ["/bin", "/lib", "/lib64", "/usr", "/etc/alternatives"];
+/// Device files bound into the jail, they are kept writable
+static DEVICE_BINDS: [&str; 4] = ["/dev/null", "/dev/zero", "/dev/random", "/dev/urandom"];
+
+/// The jail root only holds mount points, so it is tiny
+const JAIL_ROOT_TMPFS_OPTIONS: &str = "size=1m,mode=0755";
+const DEFAULT_SCRATCH_SIZE: u64 = 64 * 1024 * 1024;
+
+/// A filesystem view for the sandboxed process,
+/// which only contains read-only runtime paths, the executable and a scratch dir.
+/// Building the jail requires a mount namespace, which works in rootless mode as well.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JailConfigs {
+    /// Bound to the same paths inside the jail, missing paths are skipped
+    pub readonly_binds: Vec<PathBuf>,
+    /// Size cap (in bytes) of the tmpfs mounted at `JAIL_SCRATCH_PATH`
+    pub scratch_size: u64,
+}
+
+impl Default for JailConfigs {
+    fn default() -> Self {
+        Self {
+            readonly_binds: DEFAULT_RUNTIME_BINDS.iter().map(PathBuf::from).collect(),
+            scratch_size: DEFAULT_SCRATCH_SIZE,
+        }
+    }
+}
+
+impl JailConfigs {
+    pub fn add_readonly_bind(&mut self, path: PathBuf) {
+        self.readonly_binds.push(path);
+    }
+
+    pub fn get_root_path() -> PathBuf {
+        std::env::temp_dir().join(JAIL_ROOT_DIRNAME)
+    }
+
+    /// Prepare the mounts of the jail, for the forked process to `JailPlan::enter()` it.
+    /// Everything needing allocation is done here, before `fork()`.
+    pub(crate) fn prepare(&self, mount_proc: bool) -> Result<JailPlan, JudgeCoreError> {
+        let root = Self::get_root_path();
+        fs::create_dir_all(&root)?;
+
+        let scratch = root.join(JAIL_SCRATCH_PATH.trim_start_matches('/'));
+        let mut plan = JailPlan {
+            root: to_cstring(&root)?,
+            root_options: CString::new(JAIL_ROOT_TMPFS_OPTIONS)?,
+            scratch_dirs: get_dirs(&root, &scratch)?,
+            scratch: to_cstring(&scratch)?,
+            scratch_options: CString::new(format!("size={},mode=1777", self.scratch_size))?,
+            binds: vec![],
+            proc: None,
+        };
+
+        for path in self.readonly_binds.iter() {
+            if path.exists() {
+                plan.add_bind(path, &root, Some(get_locked_flags(path)?))?;
+            }
+        }
+        for path in DEVICE_BINDS.iter() {
+            let path = Path::new(path);
+            if path.exists() {
+                plan.add_bind(path, &root, None)?;
+            }
+        }
+        if mount_proc {
+            let proc = root.join("proc");
+            plan.proc = Some((get_dirs(&root, &proc)?, to_cstring(&proc)?));
+        }
+        Ok(plan)
+    }
+}
+
+/// The mounts of a jail, prepared by `JailConfigs::prepare()`.
+/// The mount points are created on the tmpfs of the root, so they are only created
+/// by `enter()` in the forked process.
+#[derive(Debug)]
+pub(crate) struct JailPlan {
+    root: CString,
+    root_options: CString,
+    // Created on the root tmpfs, parents first, so are the other dirs
+    scratch_dirs: Vec<CString>,
+    scratch: CString,
+    scratch_options: CString,
+    binds: Vec<JailBind>,
+    // The dirs to create and the mount point
+    proc: Option<(Vec<CString>, CString)>,
+}
+
+#[derive(Debug)]
+struct JailBind {
+    source: CString,
+    target: CString,
+    // Created before the target
+    dirs: Vec<CString>,
+    // Whether the target is an empty file rather than a dir
+    is_file: bool,
+    // The locked flags when remounted read-only, see `get_locked_flags()`
+    readonly_flags: Option<MsFlags>,
+}
+
+impl JailPlan {
+    /// Build the jail and `pivot_root` into it, the old root is detached afterwards.
+    /// Should be called by the forked process in a new mount namespace,
+    /// only raw syscalls are used.
+    ///
+    /// Opened file descriptors (like the redirected stdin and stdout) keep working.
+    pub(crate) fn enter(&self) -> Result<(), Errno> {
+        mount(
+            Some("tmpfs"),
+            self.root.as_c_str(),
+            Some("tmpfs"),
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+            Some(self.root_options.as_c_str()),
+        )?;
+
+        // Mounted before the binds, the executable may be placed under it
+        make_dirs(&self.scratch_dirs)?;
+        mount(
+            Some("tmpfs"),
+            self.scratch.as_c_str(),
+            Some("tmpfs"),
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+            Some(self.scratch_options.as_c_str()),
+        )?;
+
+        for bind in self.binds.iter() {
+            bind.mount()?;
+        }
+        if let Some((dirs, proc)) = &self.proc {
+            make_dirs(dirs)?;
+            mount(
+                Some("proc"),
+                proc.as_c_str(),
+                Some("proc"),
+                MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+                None::<&str>,
+            )?;
+        }
+
+        // Stack the old root on the new one, then detach it
+        chdir(self.root.as_c_str())?;
+        pivot_root(".", ".")?;
+        umount2(".", MntFlags::MNT_DETACH)?;
+        chdir("/")?;
+        mount(
+            None::<&str>,
+            "/",
+            None::<&str>,
+            MsFlags::MS_REMOUNT | MsFlags::MS_BIND | MsFlags::MS_RDONLY | MsFlags::MS_NOSUID,
+            None::<&str>,
+        )?;
+        Ok(())
+    }
+
+    /// Bind `path` to the same path under `root`.
+    fn add_bind(
+        &mut self,
+        path: &Path,
+        root: &Path,
+        readonly_flags: Option<MsFlags>,
+    ) -> Result<(), JudgeCoreError> {
+        let relative_path = path.strip_prefix("/").map_err(|_| {
+            JudgeCoreError::AnyhowError(anyhow::anyhow!("Bind path {:?} is not absolute", path))
+        })?;
+        let target = root.join(relative_path);
+        let is_file = !path.is_dir();
+        let dirs = match target.parent() {
+            Some(parent) if is_file => get_dirs(root, parent)?,
+            _ => get_dirs(root, &target)?,
+        };
+        self.binds.push(JailBind {
+            source: to_cstring(path)?,
+            target: to_cstring(&target)?,
+            dirs,
+            is_file,
+            readonly_flags,
+        });
+        Ok(())
+    }
+}
+
+impl JailBind {
+    fn mount(&self) -> Result<(), Errno> {
+        make_dirs(&self.dirs)?;
+        if self.is_file {
+            let fd = unsafe {
+                libc::open(
+                    self.target.as_ptr(),
+                    libc::O_WRONLY | libc::O_CREAT | libc::O_CLOEXEC,
+                    0o644,
+                )
+            };
+            if fd < 0 {
+                return Err(Errno::last());
+            }
+            unsafe { libc::close(fd) };
+        }
+
+        mount(
+            Some(self.source.as_c_str()),
+            self.target.as_c_str(),
+            None::<&str>,
+            MsFlags::MS_BIND | MsFlags::MS_REC,
+            None::<&str>,
+        )?;
+        if let Some(locked_flags) = self.readonly_flags {
+            // Flags of a bind mount can only be changed by remounting
+            mount(
+                None::<&str>,
+                self.target.as_c_str(),
+                None::<&str>,
+                MsFlags::MS_REMOUNT
+                    | MsFlags::MS_BIND
+                    | MsFlags::MS_RDONLY
+                    | MsFlags::MS_NOSUID
+                    | locked_flags,
+                None::<&str>,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The dirs from under `root` down to `path`, parents first.
+fn get_dirs(root: &Path, path: &Path) -> Result<Vec<CString>, JudgeCoreError> {
+    let mut dirs = path
+        .ancestors()
+        .take_while(|dir| *dir != root && dir.starts_with(root))
+        .map(to_cstring)
+        .collect::<Result<Vec<_>, _>>()?;
+    dirs.reverse();
+    Ok(dirs)
+}
+
+/// Create the dirs in order, the existing ones are skipped.
+fn make_dirs(dirs: &[CString]) -> Result<(), Errno> {
+    for dir in dirs.iter() {
+        if unsafe { libc::mkdir(dir.as_ptr(), 0o755) } != 0 && Errno::last() != Errno::EEXIST {
+            return Err(Errno::last());
+        }
+    }
+    Ok(())
+}
+
+fn to_cstring(path: &Path) -> Result<CString, JudgeCoreError> {
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+}
+
+/// In a user namespace, the flags of mounts from the host are locked,
+/// remounting fails with `EPERM` unless they are kept.
+fn get_locked_flags(path: &Path) -> Result<MsFlags, JudgeCoreError> {
+    let fs_flags = statvfs(path)?.flags();
+    let mut flags = MsFlags::empty();
+    for (fs_flag, ms_flag) in [
+        (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+        (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+        (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+        (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+        (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+    ] {
+        if fs_flags.contains(fs_flag) {
+            flags |= ms_flag;
+        }
+    }
+    Ok(flags)
+}
+
+#[test]
+fn test_get_dirs() {
+    let root = Path::new("/tmp/judger-jail");
+    assert_eq!(
+        get_dirs(root, &root.join("usr/lib")).unwrap(),
+        vec![
+            CString::new("/tmp/judger-jail/usr").unwrap(),
+            CString::new("/tmp/judger-jail/usr/lib").unwrap(),
+        ]
+    );
+    assert!(get_dirs(root, root).unwrap().is_empty());
+}
@@ -0,0 +1,155 @@
+use anyhow::anyhow;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::error::JudgeCoreError;
+
+/// Hands out the least used of `items` to each run, so that concurrent runs
+/// do not share them (e.g. a core for stable timing, or ids for per-user limits).
+/// When there are more runs than items, `lease()` shares an item
+/// while `lease_exclusive()` waits for one to be released.
+/// See `cpu::CpuPool` and `user::SandboxUserPool`.
+#[derive(Debug)]
+pub struct LeasePool<T> {
+    // For logging, e.g. "CPU pool"
+    name: &'static str,
+    items: Vec<T>,
+    // How many leases are holding each item
+    lease_counts: Mutex<Vec<u32>>,
+    // Notified whenever a lease is dropped
+    released: Condvar,
+}
+
+impl<T: Copy + fmt::Debug> LeasePool<T> {
+    /// `items` should not be empty.
+    pub fn new(name: &'static str, items: Vec<T>) -> Self {
+        let lease_counts = Mutex::new(vec![0; items.len()]);
+        Self {
+            name,
+            items,
+            lease_counts,
+            released: Condvar::new(),
+        }
+    }
+
+    /// Lease the least used item, it is released when the lease is dropped.
+    /// Runs share items when there are more runs than items in the pool.
+    pub fn lease(&self) -> Lease<'_, T> {
+        let mut lease_counts = self.lease_counts.lock().unwrap_or_else(|e| e.into_inner());
+        let (index, lease_count) = lease_counts
+            .iter_mut()
+            .enumerate()
+            .min_by_key(|(_, lease_count)| **lease_count)
+            .unwrap_or_else(|| panic!("{} should not be empty", self.name));
+        if *lease_count > 0 {
+            log::warn!(
+                "{} of {} is exhausted, sharing {:?}",
+                self.name,
+                self.items.len(),
+                self.items[index]
+            );
+        }
+        *lease_count += 1;
+        Lease {
+            item: self.items[index],
+            index,
+            pool: self,
+        }
+    }
+
+    /// Lease an item which no other lease is holding, waiting up to `timeout` for one
+    /// to be released when every item is in use.
+    pub fn lease_exclusive(&self, timeout: Duration) -> Result<Lease<'_, T>, JudgeCoreError> {
+        let deadline = Instant::now() + timeout;
+        let mut lease_counts = self.lease_counts.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if let Some(index) = lease_counts
+                .iter()
+                .position(|lease_count| *lease_count == 0)
+            {
+                lease_counts[index] += 1;
+                return Ok(Lease {
+                    item: self.items[index],
+                    index,
+                    pool: self,
+                });
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(JudgeCoreError::AnyhowError(anyhow!(
+                    "{} of {} is exhausted for {:?}",
+                    self.name,
+                    self.items.len(),
+                    timeout
+                )));
+            }
+            log::debug!("{} is exhausted, waiting for a release", self.name);
+            lease_counts = self
+                .released
+                .wait_timeout(lease_counts, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+
+    fn release(&self, index: usize) {
+        let mut lease_counts = self.lease_counts.lock().unwrap_or_else(|e| e.into_inner());
+        lease_counts[index] -= 1;
+        self.released.notify_one();
+    }
+}
+
+/// An item of a `LeasePool`, dereferencing to it.
+#[derive(Debug)]
+pub struct Lease<'a, T: Copy + fmt::Debug> {
+    item: T,
+    index: usize,
+    pool: &'a LeasePool<T>,
+}
+
+impl<T: Copy + fmt::Debug> Deref for Lease<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.item
+    }
+}
+
+impl<T: Copy + fmt::Debug> Drop for Lease<'_, T> {
+    fn drop(&mut self) {
+        self.pool.release(self.index);
+    }
+}
+
+#[test]
+fn test_lease_pool() {
+    let pool = LeasePool::new("Test pool", vec![2, 3]);
+    let first = pool.lease();
+    let second = pool.lease();
+    assert_eq!((*first, *second), (2, 3));
+    // Exhausted, so the items are shared
+    let third = pool.lease();
+    assert!(*third == 2 || *third == 3);
+    drop(first);
+    drop(third);
+    assert_eq!(*pool.lease(), 2);
+}
+
+#[test]
+fn test_lease_pool_exclusive() {
+    let pool = LeasePool::new("Test pool", vec![2]);
+    let timeout = Duration::from_millis(10);
+    let first = pool.lease_exclusive(timeout).unwrap();
+    assert_eq!(*first, 2);
+    // Never shared
+    assert!(pool.lease_exclusive(timeout).is_err());
+
+    std::thread::scope(|scope| {
+        let waiting = scope.spawn(|| *pool.lease_exclusive(Duration::from_secs(10)).unwrap());
+        std::thread::sleep(timeout);
+        drop(first);
+        assert_eq!(waiting.join().unwrap(), 2);
+    });
+}
@@ -1,14 +1,54 @@
+pub mod cancellation;
+pub mod cpu;
+pub mod jail;
+pub mod lease;
+pub mod namespace;
+pub mod seccomp;
+pub mod spawn;
+pub mod usage;
+pub mod user;
+
+use self::cancellation::CancellationToken;
+use self::cpu::{get_cpu_pool, CpuLease, SchedPolicy};
+use self::jail::{JailConfigs, JailPlan};
+use self::namespace::{
+    drop_to_mapped_user, is_rootless_supported, open_proc_dir, NamespaceConfigs, RootlessIdMaps,
+};
+use self::seccomp::{build_learning_filter, LearnedSyscall};
+use self::spawn::{
+    check_spawn, create_spawn_channel, fail_spawn, receive_spawn_report, send_notify_fd, send_pid,
+    SpawnStage,
+};
+use self::usage::{read_proc_usage, UsageSample};
+use self::user::{get_sandbox_user_pool, is_root, SandboxUserLease, SANDBOX_USER_LEASE_TIMEOUT};
+use crate::cgroup::{get_cgroup_runs_root, Cgroup};
 use crate::error::JudgeCoreError;
-use libc::{c_int, rusage, wait4, WEXITSTATUS, WSTOPPED, WTERMSIG};
-use libseccomp::ScmpFilterContext;
+use libc::{c_int, rusage, WCOREDUMP, WEXITSTATUS, WIFEXITED, WTERMSIG};
+use libseccomp::{
+    ScmpAction, ScmpArgCompare, ScmpCompareOp, ScmpFilterContext, ScmpNotifReq, ScmpNotifResp,
+    ScmpNotifRespFlags, ScmpSyscall,
+};
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::sys::epoll::{Epoll, EpollCreateFlags, EpollEvent, EpollFlags};
 use nix::sys::resource::{
     setrlimit,
-    Resource::{RLIMIT_AS, RLIMIT_CPU, RLIMIT_STACK},
+    Resource::{
+        RLIMIT_AS, RLIMIT_CORE, RLIMIT_CPU, RLIMIT_FSIZE, RLIMIT_NOFILE, RLIMIT_NPROC, RLIMIT_STACK,
+    },
 };
-use nix::unistd::{fork, ForkResult};
+use nix::sys::signal::{killpg, Signal};
+use nix::sys::time::TimeSpec;
+use nix::sys::timerfd::{ClockId, Expiration, TimerFd, TimerFlags, TimerSetTimeFlags};
+use nix::unistd::{chdir, fork, setpgid, ForkResult, Pid};
 use serde_derive::{Deserialize, Serialize};
 use std::{
-    process::Command,
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    convert::TryFrom,
+    fmt,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+    path::PathBuf,
     time::{Duration, Instant},
 };
 
@@ -17,7 +57,12 @@ pub static DEFAULT_RLIMIT_CONFIGS: RlimitConfigs = RlimitConfigs {
     as_limit: Some((64 * 1024 * 1024, 64 * 1024 * 1024)),
     cpu_limit: Some((1, 2)),
     nproc_limit: Some((1, 1)),
-    fsize_limit: Some((1024, 1024)),
+    fsize_limit: Some((8 * 1024 * 1024, 8 * 1024 * 1024)),
+    nofile_limit: Some((256, 256)),
+    core_limit: Some((0, 0)),
+    real_time_limit: Some(3),
+    max_threads: None,
+    cpu_pinning: None,
 };
 
 pub static SCRIPT_LIMIT_CONFIG: RlimitConfigs = RlimitConfigs {
@@ -25,10 +70,20 @@ pub static SCRIPT_LIMIT_CONFIG: RlimitConfigs = RlimitConfigs {
     as_limit: Some((1024 * 1024 * 1024, 1024 * 1024 * 1024)),
     cpu_limit: Some((60, 90)),
     nproc_limit: Some((1, 1)),
-    fsize_limit: Some((1024, 1024)),
+    fsize_limit: Some((8 * 1024 * 1024, 8 * 1024 * 1024)),
+    nofile_limit: Some((256, 256)),
+    core_limit: Some((0, 0)),
+    real_time_limit: Some(180),
+    max_threads: None,
+    cpu_pinning: None,
 };
 
 pub static SANDBOX_USERNAME: &str = "judger_sandbox";
+/// Seen by programs in deterministic mode, see `Sandbox::set_deterministic()`
+pub static DETERMINISTIC_HOSTNAME: &str = "judger";
+/// How many cores a single-threaded run can use when cgroup v2 is available,
+/// see `RlimitConfigs::get_cgroup_cpus()`
+pub static DEFAULT_CGROUP_CPUS: u64 = 1;
 
 #[derive(Default, Debug, Clone, Serialize)]
 pub struct RlimitConfigs {
@@ -36,7 +91,25 @@ pub struct RlimitConfigs {
     pub as_limit: Option<(u64, u64)>,
     pub cpu_limit: Option<(u64, u64)>,
     pub nproc_limit: Option<(u64, u64)>,
+    /// The unit of `fsize_limit` is byte,
+    /// a process writing past the soft limit will receive `SIGXFSZ`, see `load()`.
     pub fsize_limit: Option<(u64, u64)>,
+    pub nofile_limit: Option<(u64, u64)>,
+    pub core_limit: Option<(u64, u64)>,
+    /// The unit of `real_time_limit` is second.
+    /// Unlike the other limits, it is enforced by `Sandbox::wait()` instead of rlimit,
+    /// which makes sleeping or blocked processes killable.
+    pub real_time_limit: Option<u64>,
+    /// Threads (the main one included) a run can have at once, `None` means single-threaded.
+    /// Raises `nproc_limit` (also used as `pids.max`) when it is lower,
+    /// and allows creating threads but not processes in the seccomp filter.
+    /// CPU time is always counted across all threads,
+    /// and the threads can use as many cores in parallel unless `cpu_pinning` is set.
+    pub max_threads: Option<u64>,
+    /// Pin the run to a core leased from the CPU pool (see `cpu::get_cpu_pool()`)
+    /// with the scheduling policy, which makes timing stable on a busy host.
+    /// `None` lets the run be scheduled anywhere as usual.
+    pub cpu_pinning: Option<SchedPolicy>,
 }
 
 impl RlimitConfigs {
@@ -45,7 +118,9 @@ impl RlimitConfigs {
     /// One thing should be noted is that `RLIMIT_CPU` is set to +1 second of the given value.
     /// This is because rlimit will kills the process when CPU almost reaches the limit,
     /// which can have a few milliseconds of deviation.
-    pub fn load(&self) -> Result<(), JudgeCoreError> {
+    /// Similarly, `RLIMIT_FSIZE` is set to +1 byte, so that output of exactly `fsize_limit`
+    /// is allowed while any more can be told from the size of the file.
+    pub fn load(&self) -> Result<(), Errno> {
         if let Some(stack_limit) = self.stack_limit {
             setrlimit(RLIMIT_STACK, stack_limit.0, stack_limit.1)?;
         }
@@ -55,21 +130,98 @@ impl RlimitConfigs {
         if let Some(cpu_limit) = self.cpu_limit {
             setrlimit(RLIMIT_CPU, cpu_limit.0 + 1, cpu_limit.1 + 1)?;
         }
+        if let Some(nproc_limit) = self.get_nproc_limit() {
+            setrlimit(RLIMIT_NPROC, nproc_limit.0, nproc_limit.1)?;
+        }
+        if let Some(fsize_limit) = self.fsize_limit {
+            setrlimit(
+                RLIMIT_FSIZE,
+                fsize_limit.0.saturating_add(1),
+                fsize_limit.1.saturating_add(1),
+            )?;
+        }
+        if let Some(nofile_limit) = self.nofile_limit {
+            setrlimit(RLIMIT_NOFILE, nofile_limit.0, nofile_limit.1)?;
+        }
+        if let Some(core_limit) = self.core_limit {
+            setrlimit(RLIMIT_CORE, core_limit.0, core_limit.1)?;
+        }
         Ok(())
     }
 
+    /// `nproc_limit` raised to `max_threads`, since threads count as processes
+    /// for both `RLIMIT_NPROC` and `pids.max`.
+    pub fn get_nproc_limit(&self) -> Option<(u64, u64)> {
+        let max_threads = self.max_threads.unwrap_or(1);
+        self.nproc_limit
+            .map(|(soft, hard)| (soft.max(max_threads), hard.max(max_threads)))
+    }
+
+    /// The bandwidth (`cpu.max`) of a run in cores, one per allowed thread,
+    /// so that the threads can run in parallel.
+    pub fn get_cgroup_cpus(&self) -> u64 {
+        self.max_threads.unwrap_or(1).max(DEFAULT_CGROUP_CPUS)
+    }
+
+    pub fn allows_threads(&self) -> bool {
+        self.max_threads.is_some_and(|max_threads| max_threads > 1)
+    }
+
     pub fn get_cpu_limit_duration(&self) -> Option<std::time::Duration> {
         self.cpu_limit
             .map(|(soft, _)| std::time::Duration::from_secs(soft))
     }
+
+    pub fn get_real_time_limit_duration(&self) -> Option<std::time::Duration> {
+        self.real_time_limit.map(std::time::Duration::from_secs)
+    }
+
+    pub fn get_memory_limit_bytes(&self) -> Option<u64> {
+        self.as_limit.map(|(soft, _)| soft)
+    }
+
+    pub fn get_output_limit_bytes(&self) -> Option<u64> {
+        self.fsize_limit.map(|(soft, _)| soft)
+    }
 }
 
 pub struct Sandbox {
     pub child_pid: i32,
+    // The process running the program, which is forked from `child_pid`
+    // when entering a new pid namespace, see `NamespaceConfigs::enter()`
+    program_pid: i32,
 
-    user_id: u32,
+    // Only leased when running as root, which can switch to the sandbox users
+    user_lease: Option<SandboxUserLease<'static>>,
+    cpu_lease: Option<CpuLease<'static>>,
     rlimit_configs: Option<RlimitConfigs>,
-    scmp_filter: Option<ScmpFilterContext>,
+    scmp_filter: Option<SendFilterContext>,
+    cgroup: Option<Cgroup>,
+    namespace_configs: NamespaceConfigs,
+    jail_configs: Option<JailConfigs>,
+    // Prepared before `fork()` to avoid allocating in the child process
+    jail_plan: Option<JailPlan>,
+    // Set when a user namespace is used in place of root privilege,
+    // the program keeps the ids of the judger then
+    rootless: Option<RootlessIdMaps>,
+    cwd: Option<PathBuf>,
+    seccomp_notify_fd: Option<OwnedFd>,
+    // `cgroup.procs` opened before `fork()`, so that the check of writing to it
+    // uses the credentials of the judger rather than the sandboxed process
+    cgroup_procs_fd: Option<OwnedFd>,
+    pidfd: Option<OwnedFd>,
+    // Created on spawn, see `get_event_fd()`
+    supervisor_fds: Option<SupervisorFds>,
+    // Set once no process is using the seccomp filter, the notify fd is not watched then
+    seccomp_notify_closed: Cell<bool>,
+    supervisor: RefCell<SupervisorState>,
+    // Whether the child has been reaped by `wait()`
+    reaped: Cell<bool>,
+    usage_sample_interval: Option<Duration>,
+    // Whether syscalls are recorded instead of restricted
+    learning: bool,
+    deterministic: bool,
+    cancellation_token: Option<CancellationToken>,
 
     begin_time: Instant,
 }
@@ -82,105 +234,960 @@ impl Sandbox {
         let child_pid = -1;
         let begin_time = Instant::now();
 
-        // Run `id -u $SANDBOX_USERNAME` to get the user id
-        let output = Command::new("id")
-            .arg("-u")
-            .arg(SANDBOX_USERNAME)
-            .output()
-            .map_err(|e| JudgeCoreError::AnyhowError(e.into()))?;
-        let user_id = String::from_utf8(output.stdout)
-            .map_err(|e| JudgeCoreError::AnyhowError(e.into()))?
-            .trim()
-            .parse::<u32>()
-            .map_err(|e| JudgeCoreError::AnyhowError(e.into()))?;
-        if unsafe { libc::getuid() == 0 } {
-            log::debug!("Sandbox user id: {}", user_id);
+        let user_lease = if is_root() {
+            let user_lease =
+                get_sandbox_user_pool()?.lease_exclusive(SANDBOX_USER_LEASE_TIMEOUT)?;
+            log::debug!("Sandbox user uid={} gid={}", user_lease.uid, user_lease.gid);
+            Some(user_lease)
+        } else {
+            None
+        };
+
+        let cpu_lease = match rlimit_configs
+            .as_ref()
+            .and_then(|configs| configs.cpu_pinning)
+        {
+            Some(_) => Some(get_cpu_pool()?.lease()),
+            None => None,
+        };
+
+        // When cgroup v2 is available, memory is limited by `memory.max` instead of `RLIMIT_AS`,
+        // since runtimes like Go and Java reserve much more address space than they use.
+        let mut rlimit_configs = rlimit_configs;
+        let mut cgroup = None;
+        if let Some(configs) = rlimit_configs.as_mut() {
+            if get_cgroup_runs_root().is_some() {
+                cgroup = Some(create_cgroup(configs)?);
+                configs.as_limit = None;
+            }
         }
+
         Ok(Self {
-            user_id,
+            user_lease,
+            cpu_lease,
             rlimit_configs,
-            scmp_filter,
+            scmp_filter: scmp_filter.map(SendFilterContext),
+            cgroup,
+            namespace_configs: NamespaceConfigs::default(),
+            jail_configs: None,
+            jail_plan: None,
+            rootless: None,
+            cwd: None,
+            seccomp_notify_fd: None,
+            cgroup_procs_fd: None,
+            pidfd: None,
+            supervisor_fds: None,
+            seccomp_notify_closed: Cell::new(false),
+            supervisor: RefCell::new(SupervisorState::default()),
+            reaped: Cell::new(false),
+            usage_sample_interval: None,
+            learning: false,
+            cancellation_token: None,
+            deterministic: false,
             child_pid,
+            program_pid: child_pid,
             begin_time,
         })
     }
 
+    pub fn set_namespace_configs(&mut self, namespace_configs: NamespaceConfigs) {
+        self.namespace_configs = namespace_configs;
+    }
+
+    /// The jail is built in the mount namespace, which should be enabled as well.
+    pub fn set_jail_configs(&mut self, jail_configs: Option<JailConfigs>) {
+        self.jail_configs = jail_configs;
+    }
+
+    /// Changed into after entering the jail, so it is a path inside the jail if any.
+    pub fn set_cwd(&mut self, cwd: Option<PathBuf>) {
+        self.cwd = cwd;
+    }
+
+    /// Sample the usage every `interval` while waiting, into `SandboxExitInfo::usage_samples`.
+    /// `None` (the default) takes no samples.
+    pub fn set_usage_sample_interval(&mut self, interval: Option<Duration>) {
+        self.usage_sample_interval = interval;
+    }
+
+    /// Make reruns of the same program behave the same, for reproducing reports:
+    /// - address space layout randomization is disabled with `personality()`;
+    /// - the hostname is `DETERMINISTIC_HOSTNAME` in a new UTS namespace, when namespaces are available;
+    /// - the working directory is `/` unless set.
+    ///
+    /// The environment is already fixed by the executor.
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    /// Cancelling the token kills the process tree, then `wait()` returns `JudgeCoreError::Cancelled`.
+    /// Spawning fails in the same way when it is already cancelled.
+    pub fn set_cancellation_token(&mut self, cancellation_token: Option<CancellationToken>) {
+        self.cancellation_token = cancellation_token;
+    }
+
+    /// Replace the seccomp filter with one letting every syscall through after recording it
+    /// into `SandboxExitInfo::learned_syscalls`, see `seccomp::format_learned_profile()`.
+    /// Requires seccomp user notification.
+    pub fn enable_syscall_learning(&mut self) -> Result<(), JudgeCoreError> {
+        self.scmp_filter = Some(SendFilterContext(build_learning_filter()?));
+        self.learning = true;
+        Ok(())
+    }
+
+    /// Block until the child exits or is killed by `supervise()`, then reap it.
     pub fn wait(&self) -> Result<SandboxExitInfo, JudgeCoreError> {
-        let mut status: c_int = 0;
-        let mut usage: rusage = get_default_rusage();
-        unsafe {
-            wait4(self.child_pid, &mut status, WSTOPPED, &mut usage);
+        while !self.supervise()? {
+            let mut poll_fds = [PollFd::new(self.get_event_fd()?, PollFlags::POLLIN)];
+            match poll(&mut poll_fds, PollTimeout::NONE) {
+                Ok(_) | Err(Errno::EINTR) => {}
+                Err(e) => return Err(JudgeCoreError::NixErrno(e)),
+            }
+        }
+        let mut supervisor = self.supervisor.borrow_mut();
+        let event = supervisor.event.take().unwrap_or(SupervisorEvent::Exited);
+        // The exited child is not reaped yet, so its pid can not be reused by
+        // another process group while the descendants left behind are killed.
+        if let Err(e) = self.kill() {
+            log::warn!(
+                "Failed to kill the process tree of pid={}: {:?}",
+                self.child_pid,
+                e
+            );
         }
 
+        let (status, usage) = self.reap()?;
         log::info!("Detected process pid={} exit", self.child_pid);
+        if event == SupervisorEvent::Cancelled {
+            return Err(JudgeCoreError::Cancelled);
+        }
 
+        let mut resource_usage = Rusage::from(usage);
+        let mut oom_killed = false;
+        if let Some(cgroup) = &self.cgroup {
+            // cgroup accounting also covers descendants which are not reaped by the child,
+            // but charges page cache as well, which is what is left charged after the exit.
+            // The peak without it is only taken over `ru_maxrss` of the reaped processes
+            // when larger, so that caching the output never counts against the program.
+            if let Ok(memory_peak) = cgroup.get_memory_peak() {
+                let page_cache = cgroup.get_memory_file().unwrap_or(0);
+                resource_usage.max_rss = resource_usage
+                    .max_rss
+                    .max(memory_peak.saturating_sub(page_cache) as i64);
+            }
+            if let Ok(cpu_usage) = cgroup.get_cpu_usage() {
+                resource_usage.user_time = cpu_usage.user_time;
+                resource_usage.system_time = cpu_usage.system_time;
+            }
+            let oom_kill_count = cgroup.get_oom_kill_count().unwrap_or_else(|e| {
+                log::warn!(
+                    "Failed to read OOM kills of {:?}: {:?}",
+                    cgroup.get_path(),
+                    e
+                );
+                0
+            });
+            oom_killed = oom_kill_count > 0;
+        }
+
+        let exit_reason = self.get_exit_reason(status, event, oom_killed, &resource_usage);
+        log::debug!(
+            "Process pid={} exit reason: {:?}",
+            self.child_pid,
+            exit_reason
+        );
         Ok(SandboxExitInfo {
             exit_status: status,
-            exit_signal: WTERMSIG(status),
-            exit_code: WEXITSTATUS(status),
+            exit_reason,
             real_time_cost: self.begin_time.elapsed(),
-            resource_usage: Rusage::from(usage),
+            resource_usage,
+            usage_samples: std::mem::take(&mut supervisor.usage_samples),
+            learned_syscalls: std::mem::take(&mut supervisor.learned_syscalls)
+                .into_values()
+                .collect(),
         })
     }
 
+    /// The async counterpart of `wait()`, which waits for `get_event_fd()`
+    /// on the tokio runtime instead of blocking the thread.
+    #[cfg(feature = "async")]
+    pub async fn wait_async(&mut self) -> Result<SandboxExitInfo, JudgeCoreError> {
+        while !self.supervise()? {
+            wait_readable(self.get_event_fd()?.as_raw_fd()).await?;
+        }
+        self.wait()
+    }
+
+    /// Reap the child with `waitid()` on its pidfd, returning a `wait4()` style status.
+    fn reap(&self) -> Result<(c_int, rusage), JudgeCoreError> {
+        let pidfd = self.get_pidfd()?;
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let mut usage: rusage = get_default_rusage();
+        loop {
+            // Unlike the libc wrapper, the raw syscall fills the rusage as well
+            let ret = unsafe {
+                libc::syscall(
+                    libc::SYS_waitid,
+                    libc::P_PIDFD,
+                    pidfd.as_raw_fd(),
+                    &mut info as *mut libc::siginfo_t,
+                    libc::WEXITED,
+                    &mut usage as *mut rusage,
+                )
+            };
+            match ret {
+                0 => break,
+                _ if Errno::last() == Errno::EINTR => continue,
+                _ => return Err(JudgeCoreError::NixErrno(Errno::last())),
+            }
+        }
+        self.reaped.set(true);
+
+        let code = unsafe { info.si_status() };
+        let status = match info.si_code {
+            libc::CLD_EXITED => (code & 0xff) << 8,
+            libc::CLD_DUMPED => code | 0x80,
+            _ => code,
+        };
+        Ok((status, usage))
+    }
+
+    /// Kills by the sandbox itself come first, since they end up with `SIGKILL` as well.
+    fn get_exit_reason(
+        &self,
+        status: c_int,
+        event: SupervisorEvent,
+        oom_killed: bool,
+        resource_usage: &Rusage,
+    ) -> ExitReason {
+        match event {
+            SupervisorEvent::RealTimeLimitExceeded => return ExitReason::KilledByTimeLimit,
+            SupervisorEvent::RestrictedSyscall(syscall) => {
+                return ExitReason::KilledBySeccomp(Some(syscall))
+            }
+            // Cancelled runs are not judged, see `wait()`
+            SupervisorEvent::Exited | SupervisorEvent::Cancelled => {}
+        }
+        if oom_killed {
+            return ExitReason::KilledByMemoryLimit;
+        }
+        if WIFEXITED(status) {
+            return ExitReason::Exited(WEXITSTATUS(status));
+        }
+
+        let signal = WTERMSIG(status);
+        let cpu_limit = self
+            .rlimit_configs
+            .as_ref()
+            .and_then(|configs| configs.get_cpu_limit_duration());
+        let cpu_time = resource_usage.user_time + resource_usage.system_time;
+        match signal {
+            libc::SIGSYS => ExitReason::KilledBySeccomp(None),
+            // Sent at the soft limit of `RLIMIT_CPU`, and `SIGKILL` at the hard limit
+            libc::SIGXCPU => ExitReason::KilledByTimeLimit,
+            libc::SIGKILL if cpu_limit.is_some_and(|cpu_limit| cpu_time >= cpu_limit) => {
+                ExitReason::KilledByTimeLimit
+            }
+            _ => ExitReason::Signaled(signal, WCOREDUMP(status)),
+        }
+    }
+
+    /// Kill the whole process tree of the child with `SIGKILL`.
+    ///
+    /// Besides the process group of the child, the cgroup is killed as well when available,
+    /// which also covers the descendants that called `setsid()` or `setpgid()`.
+    /// The child itself is left for `wait()` to reap.
+    pub fn kill(&self) -> Result<(), JudgeCoreError> {
+        if self.child_pid <= 0 || self.reaped.get() {
+            return Ok(());
+        }
+        match killpg(Pid::from_raw(self.child_pid), Signal::SIGKILL) {
+            Ok(()) | Err(Errno::ESRCH) => {}
+            Err(e) => return Err(JudgeCoreError::NixErrno(e)),
+        }
+        if let Some(cgroup) = &self.cgroup {
+            cgroup.kill()?;
+        }
+        Ok(())
+    }
+
+    /// Usage of the whole cgroup when available, otherwise only of the process running the program.
+    /// Returns `None` when the child is gone in the meantime.
+    pub fn sample_usage(&self) -> Option<UsageSample> {
+        let elapsed = self.begin_time.elapsed();
+        let usage = match &self.cgroup {
+            // Page cache excluded, see `wait()`
+            Some(cgroup) => cgroup.get_memory_current().and_then(|memory_bytes| {
+                let page_cache = cgroup.get_memory_file()?;
+                let cpu_usage = cgroup.get_cpu_usage()?;
+                Ok((
+                    memory_bytes.saturating_sub(page_cache),
+                    cpu_usage.user_time + cpu_usage.system_time,
+                ))
+            }),
+            None => read_proc_usage(self.program_pid),
+        };
+        match usage {
+            Ok((memory_bytes, cpu_time)) => Some(UsageSample {
+                elapsed,
+                memory_bytes,
+                cpu_time,
+            }),
+            Err(e) => {
+                log::debug!(
+                    "Failed to sample usage of pid={}: {:?}",
+                    self.program_pid,
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    fn get_pidfd(&self) -> Result<&OwnedFd, JudgeCoreError> {
+        self.pidfd
+            .as_ref()
+            .ok_or_else(|| JudgeCoreError::AnyhowError(anyhow::anyhow!("Sandbox is not spawned")))
+    }
+
+    fn get_real_time_deadline(&self) -> Option<Instant> {
+        self.rlimit_configs
+            .as_ref()
+            .and_then(|configs| configs.get_real_time_limit_duration())
+            .map(|real_time_limit| self.begin_time + real_time_limit)
+    }
+
+    fn get_watched_notify_fd(&self) -> Option<&OwnedFd> {
+        self.seccomp_notify_fd
+            .as_ref()
+            .filter(|_| !self.seccomp_notify_closed.get())
+    }
+
+    /// The fds to call `supervise()` on when they are readable: the pidfd of the child,
+    /// the seccomp notify fd and the cancellation token if any.
+    fn get_watch_fds(&self) -> Vec<BorrowedFd<'_>> {
+        let mut watch_fds = vec![];
+        if let Some(pidfd) = &self.pidfd {
+            watch_fds.push(pidfd.as_fd());
+            if let Some(notify_fd) = self.get_watched_notify_fd() {
+                watch_fds.push(notify_fd.as_fd());
+            }
+            if let Some(cancellation_token) = &self.cancellation_token {
+                watch_fds.push(cancellation_token.as_fd());
+            }
+        }
+        watch_fds
+    }
+
+    /// How long the watched fds can be waited for before calling `supervise()` again,
+    /// `None` means no limit.
+    fn get_timeout(&self) -> Option<Duration> {
+        let next_sample_time = self.supervisor.borrow().next_sample_time;
+        let deadline = match (self.get_real_time_deadline(), next_sample_time) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Readable whenever `supervise()` has something to do, for watching the sandbox
+    /// in an event loop along with other fds. It is an epoll of the watched fds,
+    /// with a timer for the real time limit and the next usage sample.
+    pub fn get_event_fd(&self) -> Result<BorrowedFd<'_>, JudgeCoreError> {
+        self.supervisor_fds
+            .as_ref()
+            .map(|supervisor_fds| supervisor_fds.epoll.0.as_fd())
+            .ok_or_else(|| JudgeCoreError::AnyhowError(anyhow::anyhow!("Sandbox is not spawned")))
+    }
+
+    fn create_supervisor_fds(&self) -> Result<SupervisorFds, JudgeCoreError> {
+        let epoll = Epoll::new(EpollCreateFlags::EPOLL_CLOEXEC)?;
+        let timer = TimerFd::new(ClockId::CLOCK_MONOTONIC, TimerFlags::TFD_CLOEXEC)?;
+        for fd in self.get_watch_fds().into_iter().chain([timer.as_fd()]) {
+            let event = EpollEvent::new(EpollFlags::EPOLLIN, fd.as_raw_fd() as u64);
+            epoll.add(fd, event)?;
+        }
+        Ok(SupervisorFds { epoll, timer })
+    }
+
+    /// Expire the timer after `get_timeout()`, which also clears the last expiration.
+    fn arm_timer(&self) -> Result<(), JudgeCoreError> {
+        let timer = match &self.supervisor_fds {
+            Some(supervisor_fds) => &supervisor_fds.timer,
+            None => return Ok(()),
+        };
+        match self.get_timeout() {
+            // Setting a zero timeout disarms the timer instead
+            Some(timeout) => timer.set(
+                Expiration::OneShot(TimeSpec::from(timeout.max(Duration::from_nanos(1)))),
+                TimerSetTimeFlags::empty(),
+            )?,
+            None => timer.unset()?,
+        }
+        Ok(())
+    }
+
+    /// Poll the watched fds, returning whether the child exited and the events of the notify fd.
+    fn poll_watch_fds(&self) -> Result<(bool, Option<PollFlags>), JudgeCoreError> {
+        let mut poll_fds = self
+            .get_watch_fds()
+            .into_iter()
+            .map(|fd| PollFd::new(fd, PollFlags::POLLIN))
+            .collect::<Vec<_>>();
+        loop {
+            match poll(&mut poll_fds, PollTimeout::ZERO) {
+                Ok(_) => break,
+                Err(Errno::EINTR) => continue,
+                Err(e) => return Err(JudgeCoreError::NixErrno(e)),
+            }
+        }
+        let exited = poll_fds[0].revents().is_some_and(|r| !r.is_empty());
+        // Follows the pidfd in the watched fds
+        let notify_revents = match self.get_watched_notify_fd() {
+            Some(_) => poll_fds[1].revents(),
+            None => None,
+        };
+        Ok((exited, notify_revents))
+    }
+
+    /// Handle what happened to the child without blocking:
+    /// the real time limit is enforced, restricted syscalls reported by seccomp user notification
+    /// kill the process tree, and the usage is sampled.
+    /// Returns `true` once the child exited or is killed, then `wait()` only has to reap it.
+    /// Meant to be called whenever `get_event_fd()` is readable.
+    pub fn supervise(&self) -> Result<bool, JudgeCoreError> {
+        self.get_pidfd()?;
+        let mut supervisor = self.supervisor.borrow_mut();
+        if supervisor.event.is_some() {
+            return Ok(true);
+        }
+        let (exited, notify_revents) = self.poll_watch_fds()?;
+        if exited {
+            supervisor.event = Some(SupervisorEvent::Exited);
+            return Ok(true);
+        }
+        if self
+            .cancellation_token
+            .as_ref()
+            .is_some_and(|token| token.is_cancelled())
+        {
+            log::info!("Process pid={} is cancelled", self.child_pid);
+            self.kill()?;
+            supervisor.event = Some(SupervisorEvent::Cancelled);
+            return Ok(true);
+        }
+        if let (Some(notify_fd), Some(revents)) = (&self.seccomp_notify_fd, notify_revents) {
+            if revents.contains(PollFlags::POLLIN) {
+                if let Some(event) =
+                    self.handle_notification(notify_fd, &mut supervisor.learned_syscalls)?
+                {
+                    supervisor.event = Some(event);
+                    return Ok(true);
+                }
+            } else if !revents.is_empty() {
+                // No process is using the filter anymore,
+                // and the hang-up would keep the event fd readable
+                self.seccomp_notify_closed.set(true);
+                if let Some(supervisor_fds) = &self.supervisor_fds {
+                    supervisor_fds.epoll.delete(notify_fd)?;
+                }
+            }
+        }
+
+        let now = Instant::now();
+        if self
+            .get_real_time_deadline()
+            .is_some_and(|deadline| now >= deadline)
+        {
+            log::info!(
+                "Process pid={} exceeded real time limit {:?}",
+                self.child_pid,
+                self.rlimit_configs
+                    .as_ref()
+                    .and_then(|configs| configs.get_real_time_limit_duration())
+            );
+            self.kill()?;
+            supervisor.event = Some(SupervisorEvent::RealTimeLimitExceeded);
+            return Ok(true);
+        }
+        if let (Some(interval), Some(sample_time)) =
+            (self.usage_sample_interval, supervisor.next_sample_time)
+        {
+            if now >= sample_time {
+                let usage_sample = self.sample_usage();
+                supervisor.usage_samples.extend(usage_sample);
+                // Skip the missed ones if sampling falls behind
+                supervisor.next_sample_time = Some(now.max(sample_time) + interval);
+            }
+        }
+        drop(supervisor);
+        self.arm_timer()?;
+        Ok(false)
+    }
+
+    /// In learning mode the syscall is recorded and continued,
+    /// otherwise it is restricted and the process tree is killed.
+    fn handle_notification(
+        &self,
+        notify_fd: &OwnedFd,
+        learned_syscalls: &mut BTreeMap<String, LearnedSyscall>,
+    ) -> Result<Option<SupervisorEvent>, JudgeCoreError> {
+        let req = match ScmpNotifReq::receive(notify_fd.as_raw_fd()) {
+            Ok(req) => req,
+            // The notifying process may have been killed in the meantime
+            Err(e) => {
+                log::debug!("Failed to receive seccomp notification: {:?}", e);
+                return Ok(None);
+            }
+        };
+        let syscall = req
+            .data
+            .syscall
+            .get_name()
+            .unwrap_or_else(|_| i32::from(req.data.syscall).to_string());
+        if self.learning {
+            LearnedSyscall::record(learned_syscalls, syscall, req.data.args);
+            let resp = ScmpNotifResp::new_continue(req.id, ScmpNotifRespFlags::empty());
+            // Fails when the process is killed in the meantime
+            if let Err(e) = resp.respond(notify_fd.as_raw_fd()) {
+                log::debug!("Failed to respond seccomp notification: {:?}", e);
+            }
+            return Ok(None);
+        }
+        log::info!(
+            "Process pid={} called restricted syscall {}",
+            self.child_pid,
+            syscall
+        );
+        self.kill()?;
+        Ok(Some(SupervisorEvent::RestrictedSyscall(syscall)))
+    }
+
+    /// Returns once the program is executed, or `JudgeCoreError::SpawnFailed`
+    /// when the child fails before that.
+    ///
     /// WARNING:   
     /// Unsafe to use `println!()` (or `unwrap()`) in child process.
     /// See more in `fork()` document.
+    /// `before_limit` redirects the stdio, and `after_limit` only returns if it fails to exec.
     pub fn spawn(
         &mut self,
-        before_limit: impl Fn(),
+        before_limit: impl Fn() -> Result<(), Errno>,
         after_limit: impl Fn(),
     ) -> Result<i32, JudgeCoreError> {
+        if self
+            .cancellation_token
+            .as_ref()
+            .is_some_and(|token| token.is_cancelled())
+        {
+            return Err(JudgeCoreError::Cancelled);
+        }
+        // Without root, the same isolation is built in a user namespace when possible
+        let is_root = self.user_lease.is_some();
+        self.rootless = if !is_root && is_rootless_supported() {
+            Some(RootlessIdMaps::new())
+        } else {
+            None
+        };
+        if self.namespace_configs.is_enabled() && !is_root && self.rootless.is_none() {
+            log::warn!(
+                "Creating namespaces requires root or rootless mode, namespaces and jail are ignored"
+            );
+            self.namespace_configs = NamespaceConfigs::default();
+            self.jail_configs = None;
+        }
+        if !is_root && self.rootless.is_none() {
+            // Otherwise `RLIMIT_NPROC` counts every process of the judger's user
+            if let Some(rlimit_configs) = self.rlimit_configs.as_mut() {
+                if rlimit_configs.nproc_limit.take().is_some() {
+                    log::debug!("RLIMIT_NPROC is ignored without a dedicated user");
+                }
+            }
+        }
+        if self.deterministic {
+            if is_root || self.rootless.is_some() {
+                self.namespace_configs.uts = true;
+            } else {
+                log::warn!("The hostname is not fixed without a UTS namespace");
+            }
+            if self.cwd.is_none() {
+                self.cwd = Some(PathBuf::from("/"));
+            }
+        }
+        if self.jail_configs.is_some() && !self.namespace_configs.mount {
+            return Err(JudgeCoreError::AnyhowError(anyhow::anyhow!(
+                "Jail requires the mount namespace"
+            )));
+        }
+        let (supervisor_end, child_end) = create_spawn_channel()?;
+        if let Some(SendFilterContext(scmp_filter)) = self.scmp_filter.as_mut() {
+            // Only for reporting to the supervisor after the filter is loaded
+            scmp_filter.add_rule_conditional_exact(
+                ScmpAction::Allow,
+                ScmpSyscall::from_name("sendmsg")?,
+                &[ScmpArgCompare::new(
+                    0,
+                    ScmpCompareOp::Equal,
+                    child_end.as_raw_fd() as u64,
+                )],
+            )?;
+        }
+        if let Some(cgroup) = &self.cgroup {
+            self.cgroup_procs_fd = Some(open_cgroup_procs(cgroup)?);
+        }
+        self.jail_plan = match &self.jail_configs {
+            Some(jail_configs) => Some(jail_configs.prepare(self.namespace_configs.pid)?),
+            None => None,
+        };
+
         let now = Instant::now();
         match unsafe { fork() } {
             Ok(ForkResult::Parent { child, .. }) => {
                 log::info!("Forked child pid={}", child);
+                // Also set in the child, whichever runs first avoids the race.
+                // It may fail with EACCES once the child has called `execve()`.
+                let _ = setpgid(child, child);
                 self.child_pid = child.as_raw();
+                self.program_pid = self.child_pid;
                 self.begin_time = now;
+                self.cgroup_procs_fd = None;
+                self.jail_plan = None;
+                self.pidfd = Some(pidfd_open(self.child_pid)?);
+                self.supervisor.get_mut().next_sample_time =
+                    self.usage_sample_interval.map(|interval| now + interval);
+                drop(child_end);
+                match receive_spawn_report(&supervisor_end) {
+                    Ok(report) => {
+                        self.seccomp_notify_fd = report.notify_fd;
+                        if let Some(program_pid) = report.program_pid {
+                            self.program_pid = program_pid;
+                        }
+                    }
+                    Err(e) => {
+                        log::warn!("Failed to spawn pid={}: {:?}", self.child_pid, e);
+                        self.kill()?;
+                        self.reap()?;
+                        return Err(e);
+                    }
+                }
+                // Restricted syscalls would fail with ENOSYS instead of being reported,
+                // so failing to send the notify fd fails the spawn in the child.
+                // It is only missing when no rule of the filter notifies.
+                if self.learning && self.seccomp_notify_fd.is_none() {
+                    self.kill()?;
+                    self.reap()?;
+                    return Err(JudgeCoreError::SpawnFailed(
+                        SpawnStage::SendNotifyFd,
+                        Errno::EBADF,
+                    ));
+                }
+                self.supervisor_fds = Some(self.create_supervisor_fds()?);
+                self.arm_timer()?;
                 Ok(child.as_raw())
             }
-            // child process should not return to do things outside `spawn()`
+            // child process should not return to do things outside `spawn()`,
+            // and failures are reported with `fail_spawn()` instead of panicking
             Ok(ForkResult::Child) => {
-                before_limit();
-                // If current user is root
-                // setuid to the user
-                if unsafe { libc::getuid() == 0 } {
+                let channel = child_end.as_raw_fd();
+                unsafe { libc::close(supervisor_end.as_raw_fd()) };
+                // Put the child in its own process group,
+                // so that its descendants can be killed together.
+                check_spawn(
+                    channel,
+                    SpawnStage::SetProcessGroup,
+                    setpgid(Pid::from_raw(0), Pid::from_raw(0)),
+                );
+                check_spawn(channel, SpawnStage::Redirect, before_limit());
+                if let Some(cpu_lease) = &self.cpu_lease {
+                    check_spawn(channel, SpawnStage::PinCpu, cpu_lease.pin());
+                }
+                if let Some(sched_policy) = self
+                    .rlimit_configs
+                    .as_ref()
+                    .and_then(|configs| configs.cpu_pinning)
+                {
+                    check_spawn(channel, SpawnStage::SetSchedPolicy, sched_policy.apply());
+                }
+                let proc_dir = self
+                    .rootless
+                    .as_ref()
+                    .map(|_| check_spawn(channel, SpawnStage::OpenProc, open_proc_dir()));
+                if self.rootless.is_some() || self.namespace_configs.is_enabled() {
+                    check_spawn(
+                        channel,
+                        SpawnStage::EnterNamespaces,
+                        self.namespace_configs.enter(
+                            self.rootless.as_ref().map(|id_maps| &id_maps.root),
+                            Some(channel),
+                        ),
+                    );
+                }
+                if self.namespace_configs.pid {
+                    check_spawn(channel, SpawnStage::ReportPid, send_pid(channel));
+                }
+                // Only the process running the program joins the cgroup, which is forked
+                // by `enter()` for a pid namespace, so the processes waiting for it
+                // are not counted in `pids.max`
+                if let Some(cgroup_procs_fd) = &self.cgroup_procs_fd {
+                    check_spawn(
+                        channel,
+                        SpawnStage::JoinCgroup,
+                        join_cgroup(cgroup_procs_fd),
+                    );
+                }
+                // Still privileged in the UTS namespace here
+                if self.deterministic && self.namespace_configs.uts {
+                    let hostname = DETERMINISTIC_HOSTNAME.as_bytes();
+                    if unsafe { libc::sethostname(hostname.as_ptr().cast(), hostname.len()) } != 0 {
+                        fail_spawn(channel, SpawnStage::SetHostname, Errno::last());
+                    }
+                }
+                if let Some(jail_plan) = &self.jail_plan {
+                    check_spawn(channel, SpawnStage::EnterJail, jail_plan.enter());
+                }
+                // In rootless mode the caller is root of the user namespace,
+                // so the ids of the judger are mapped back in a nested one.
+                // Otherwise if current user is root,
+                // drop the supplementary groups, then setgid and setuid to the leased ids
+                if let (Some(proc_dir), Some(id_maps)) = (&proc_dir, &self.rootless) {
+                    check_spawn(
+                        channel,
+                        SpawnStage::DropPrivileges,
+                        drop_to_mapped_user(proc_dir, &id_maps.user),
+                    );
+                } else if let Some(user_lease) = &self.user_lease {
                     unsafe {
-                        let res = libc::setuid(self.user_id);
-                        if res != 0 {
-                            libc::_exit(1);
+                        let gid = user_lease.gid;
+                        if libc::setgroups(1, &gid) != 0
+                            || libc::setgid(gid) != 0
+                            || libc::setuid(user_lease.uid) != 0
+                        {
+                            fail_spawn(channel, SpawnStage::DropPrivileges, Errno::last());
+                        }
+                    }
+                }
+                if let Some(cwd) = &self.cwd {
+                    check_spawn(channel, SpawnStage::ChangeDirectory, chdir(cwd));
+                }
+                if self.deterministic {
+                    unsafe {
+                        // 0xffffffff only queries the current persona
+                        let persona = libc::personality(0xffffffff);
+                        if persona == -1
+                            || libc::personality(
+                                (persona | libc::ADDR_NO_RANDOMIZE) as libc::c_ulong,
+                            ) == -1
+                        {
+                            fail_spawn(channel, SpawnStage::DisableRandomization, Errno::last());
                         }
                     }
                 }
                 if let Some(rlimit_configs) = &self.rlimit_configs {
-                    rlimit_configs
-                        .load()
-                        .expect("Failed to load rlimit configs");
+                    check_spawn(channel, SpawnStage::LoadRlimits, rlimit_configs.load());
                 }
-                if let Some(scmp_filter) = &self.scmp_filter {
-                    scmp_filter.load().expect("Failed to load seccomp filter");
+                if let Some(SendFilterContext(scmp_filter)) = &self.scmp_filter {
+                    if scmp_filter.load().is_err() {
+                        fail_spawn(channel, SpawnStage::LoadSeccomp, Errno::last());
+                    }
+                    // Fails when no rule of the filter notifies
+                    if let Ok(notify_fd) = scmp_filter.get_notify_fd() {
+                        check_spawn(
+                            channel,
+                            SpawnStage::SendNotifyFd,
+                            send_notify_fd(channel, notify_fd),
+                        );
+                    }
                 }
                 after_limit();
-                unsafe { libc::_exit(0) };
+                // Only returns when `execve()` fails
+                fail_spawn(channel, SpawnStage::Exec, Errno::last())
             }
             Err(e) => Err(JudgeCoreError::NixErrno(e)),
         }
     }
 }
 
+impl Drop for Sandbox {
+    /// Never leave the process tree running, e.g. when judging fails before `wait()`.
+    fn drop(&mut self) {
+        if self.child_pid <= 0 || self.reaped.get() {
+            return;
+        }
+        log::debug!("Killing unreaped process pid={}", self.child_pid);
+        if let Err(e) = self.kill() {
+            log::warn!(
+                "Failed to kill the process tree of pid={}: {:?}",
+                self.child_pid,
+                e
+            );
+        }
+        unsafe {
+            libc::waitpid(self.child_pid, std::ptr::null_mut(), 0);
+        }
+    }
+}
+
+/// `ScmpFilterContext` only holds a pointer to the context allocated by libseccomp,
+/// which is never shared, so the sandbox can be moved across threads (e.g. by async tasks).
+struct SendFilterContext(ScmpFilterContext);
+
+unsafe impl Send for SendFilterContext {}
+
+#[derive(Debug, PartialEq, Eq)]
+enum SupervisorEvent {
+    Exited,
+    RealTimeLimitExceeded,
+    RestrictedSyscall(String),
+    Cancelled,
+}
+
+/// See `Sandbox::get_event_fd()`.
+struct SupervisorFds {
+    epoll: Epoll,
+    timer: TimerFd,
+}
+
+/// Kept by `Sandbox::supervise()` between calls.
+#[derive(Debug, Default)]
+struct SupervisorState {
+    // Set once the child exited or is killed
+    event: Option<SupervisorEvent>,
+    next_sample_time: Option<Instant>,
+    usage_samples: Vec<UsageSample>,
+    learned_syscalls: BTreeMap<String, LearnedSyscall>,
+}
+
+/// Why the sandboxed process terminated.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExitReason {
+    /// Exited normally with the exit code
+    Exited(c_int),
+    /// Terminated by the signal, and whether a core was dumped
+    Signaled(c_int, bool),
+    /// Killed for reaching the CPU time limit or the real time limit
+    KilledByTimeLimit,
+    /// Killed by the OOM killer of the cgroup, only detected when cgroup v2 is available
+    KilledByMemoryLimit,
+    /// Killed for calling a restricted syscall, whose name is only known
+    /// when seccomp user notification is supported
+    KilledBySeccomp(Option<String>),
+}
+
+impl ExitReason {
+    pub fn get_restricted_syscall(&self) -> Option<String> {
+        match self {
+            Self::KilledBySeccomp(syscall) => syscall.clone(),
+            _ => None,
+        }
+    }
+
+    /// Why the program crashed, `None` if it exited with 0 or was killed by the sandbox.
+    /// `stderr` tells a failed allocation from other aborts and exits,
+    /// see `ALLOCATION_FAILURE_MESSAGES`.
+    pub fn get_crash_reason(&self, stderr: Option<&str>) -> Option<CrashReason> {
+        let is_allocation_failure = stderr.is_some_and(|stderr| {
+            ALLOCATION_FAILURE_MESSAGES
+                .iter()
+                .any(|message| stderr.contains(message))
+        });
+        match *self {
+            Self::Exited(0) => None,
+            Self::Exited(_) if is_allocation_failure => Some(CrashReason::BadAlloc),
+            Self::Exited(code) => Some(CrashReason::NonZeroExit(code)),
+            Self::Signaled(signal, _) => Some(match signal {
+                libc::SIGSEGV => CrashReason::SegmentationFault,
+                libc::SIGFPE => CrashReason::ArithmeticError,
+                libc::SIGABRT if is_allocation_failure => CrashReason::BadAlloc,
+                libc::SIGABRT => CrashReason::Aborted,
+                libc::SIGBUS => CrashReason::BusError,
+                libc::SIGILL => CrashReason::IllegalInstruction,
+                _ => CrashReason::Signaled(
+                    Signal::try_from(signal)
+                        .map(|signal| signal.as_str().to_string())
+                        .unwrap_or_else(|_| signal.to_string()),
+                ),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// What the runtimes print when an allocation fails, which aborts or exits the program:
+/// an uncaught `std::bad_alloc` of C++, Rust's `handle_alloc_error()` and Python's `MemoryError`
+pub static ALLOCATION_FAILURE_MESSAGES: [&str; 3] =
+    ["std::bad_alloc", "memory allocation of", "MemoryError"];
+
+/// Classified abnormal termination of a program, shown to users with `Display`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CrashReason {
+    /// Exited with the non-zero code, e.g. 101 for Rust panics
+    NonZeroExit(c_int),
+    /// `SIGSEGV`
+    SegmentationFault,
+    /// `SIGFPE`
+    ArithmeticError,
+    /// Aborted or exited after a failed allocation, see `ALLOCATION_FAILURE_MESSAGES`
+    BadAlloc,
+    /// `SIGABRT`
+    Aborted,
+    /// `SIGBUS`
+    BusError,
+    /// `SIGILL`
+    IllegalInstruction,
+    /// Terminated by any other signal, with its name
+    Signaled(String),
+}
+
+impl fmt::Display for CrashReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NonZeroExit(code) => write!(f, "Exited with code {}", code),
+            Self::SegmentationFault => write!(
+                f,
+                "Segmentation fault (SIGSEGV): invalid memory access, \
+                 e.g. a null pointer, an out-of-bounds index or a stack overflow"
+            ),
+            Self::ArithmeticError => write!(
+                f,
+                "Arithmetic error (SIGFPE): usually an integer division by zero"
+            ),
+            Self::BadAlloc => write!(
+                f,
+                "Memory allocation failed, the memory limit may be reached"
+            ),
+            Self::Aborted => write!(
+                f,
+                "Aborted (SIGABRT): e.g. a failed assertion or an uncaught exception"
+            ),
+            Self::BusError => write!(f, "Bus error (SIGBUS): misaligned memory access"),
+            Self::IllegalInstruction => write!(
+                f,
+                "Illegal instruction (SIGILL): e.g. a function without a return value"
+            ),
+            Self::Signaled(signal) => write!(f, "Terminated by {}", signal),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SandboxExitInfo {
+    /// The raw status from `wait4()`, prefer `exit_reason`
     pub exit_status: c_int,
-    pub exit_signal: c_int,
-    pub exit_code: c_int,
+    pub exit_reason: ExitReason,
     pub real_time_cost: Duration,
     pub resource_usage: Rusage,
+    /// Taken while waiting, empty unless `Sandbox::set_usage_sample_interval()` is set
+    pub usage_samples: Vec<UsageSample>,
+    /// Sorted by name, empty unless `Sandbox::enable_syscall_learning()` is called
+    pub learned_syscalls: Vec<LearnedSyscall>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Rusage {
     pub user_time: Duration,
     pub system_time: Duration,
+    /// Peak resident set size in bytes. With cgroup v2 the peak of the whole cgroup
+    /// without page cache is taken when larger, see `Sandbox::wait()`.
     pub max_rss: i64,
     pub page_faults: i64,
     pub involuntary_context_switches: i64,
@@ -198,7 +1205,8 @@ impl From<rusage> for Rusage {
                 rusage.ru_stime.tv_sec as u64,
                 rusage.ru_stime.tv_usec as u32 * 1000,
             ),
-            max_rss: rusage.ru_maxrss,
+            // `ru_maxrss` is given in kilobytes
+            max_rss: rusage.ru_maxrss * 1024,
             page_faults: rusage.ru_majflt,
             involuntary_context_switches: rusage.ru_nivcsw,
             voluntary_context_switches: rusage.ru_nvcsw,
@@ -206,6 +1214,64 @@ impl From<rusage> for Rusage {
     }
 }
 
+fn create_cgroup(rlimit_configs: &RlimitConfigs) -> Result<Cgroup, JudgeCoreError> {
+    let cgroup = Cgroup::new()?;
+    if let Some(memory_limit) = rlimit_configs.get_memory_limit_bytes() {
+        cgroup.set_memory_max(memory_limit)?;
+    }
+    if let Some((nproc_limit, _)) = rlimit_configs.get_nproc_limit() {
+        cgroup.set_pids_max(nproc_limit)?;
+    }
+    cgroup.set_cpu_max(rlimit_configs.get_cgroup_cpus())?;
+    Ok(cgroup)
+}
+
+/// Open `cgroup.procs` of the cgroup for `join_cgroup()`.
+fn open_cgroup_procs(cgroup: &Cgroup) -> Result<OwnedFd, JudgeCoreError> {
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(cgroup.get_procs_path())?;
+    Ok(OwnedFd::from(file))
+}
+
+/// Move the calling process into the cgroup by writing `0` to its `cgroup.procs`.
+/// Only raw syscalls are used since it runs in the forked child.
+fn join_cgroup(cgroup_procs_fd: &OwnedFd) -> Result<(), Errno> {
+    let written = unsafe {
+        libc::write(
+            cgroup_procs_fd.as_raw_fd(),
+            b"0".as_ptr() as *const libc::c_void,
+            1,
+        )
+    };
+    match written {
+        1 => Ok(()),
+        -1 => Err(Errno::last()),
+        _ => Err(Errno::EIO),
+    }
+}
+
+fn pidfd_open(pid: i32) -> Result<OwnedFd, JudgeCoreError> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+    if fd < 0 {
+        return Err(JudgeCoreError::NixErrno(Errno::last()));
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+/// Wait until `fd` is readable.
+#[cfg(feature = "async")]
+async fn wait_readable(fd: RawFd) -> Result<(), JudgeCoreError> {
+    use tokio::io::{unix::AsyncFd, Interest};
+
+    // Registered for this wait only, the readiness is cleared by the caller
+    // SAFETY: the fd is owned by the sandbox, which outlives this wait
+    let async_fd = unsafe { AsyncFd::register_with_interest(fd, Interest::READABLE) }
+        .map_err(std::io::Error::from)?;
+    let _ = async_fd.readable().await?;
+    Ok(())
+}
+
 fn get_default_rusage() -> rusage {
     rusage {
         ru_utime: libc::timeval {
@@ -232,3 +1298,34 @@ fn get_default_rusage() -> rusage {
         ru_nivcsw: 0,
     }
 }
+
+#[test]
+fn test_crash_reason() {
+    assert_eq!(ExitReason::Exited(0).get_crash_reason(None), None);
+    assert_eq!(
+        ExitReason::Exited(101).get_crash_reason(None),
+        Some(CrashReason::NonZeroExit(101))
+    );
+    let abort = ExitReason::Signaled(libc::SIGABRT, false);
+    assert_eq!(abort.get_crash_reason(None), Some(CrashReason::Aborted));
+    assert_eq!(
+        abort.get_crash_reason(Some(
+            "terminate called after throwing an instance of 'std::bad_alloc'"
+        )),
+        Some(CrashReason::BadAlloc)
+    );
+    assert_eq!(
+        abort.get_crash_reason(Some("memory allocation of 1048576 bytes failed")),
+        Some(CrashReason::BadAlloc)
+    );
+    assert_eq!(
+        ExitReason::Exited(1).get_crash_reason(Some(
+            "Traceback (most recent call last):\n  File \"a.py\", line 1, in <module>\nMemoryError"
+        )),
+        Some(CrashReason::BadAlloc)
+    );
+    assert_eq!(
+        ExitReason::Signaled(libc::SIGPIPE, false).get_crash_reason(None),
+        Some(CrashReason::Signaled("SIGPIPE".to_string()))
+    );
+}
@@ -0,0 +1,331 @@
+use nix::errno::Errno;
+use nix::fcntl::{open, openat, OFlag};
+use nix::mount::{mount, MsFlags};
+use nix::sched::{unshare, CloneFlags};
+use nix::sys::stat::Mode;
+use nix::unistd::{fork, getgid, getuid, pipe, read, write, ForkResult};
+use serde_derive::{Deserialize, Serialize};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::OnceLock;
+
+/// Linux namespaces to be created for a sandboxed process.
+/// Creating namespaces requires root privilege,
+/// or an unprivileged user namespace in rootless mode (see `is_rootless_supported()`).
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NamespaceConfigs {
+    /// Hide other processes, the sandboxed process becomes the init (pid 1) of the namespace.
+    pub pid: bool,
+    /// Only an unconfigured loopback device is available, no route to anywhere.
+    pub net: bool,
+    /// Mounts become private, `/proc` is remounted when `pid` is also enabled.
+    pub mount: bool,
+    pub ipc: bool,
+    pub uts: bool,
+}
+
+impl NamespaceConfigs {
+    pub fn all() -> Self {
+        Self {
+            pid: true,
+            net: true,
+            mount: true,
+            ipc: true,
+            uts: true,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.pid || self.net || self.mount || self.ipc || self.uts
+    }
+
+    fn get_clone_flags(&self) -> CloneFlags {
+        let mut flags = CloneFlags::empty();
+        if self.pid {
+            flags |= CloneFlags::CLONE_NEWPID;
+        }
+        if self.net {
+            flags |= CloneFlags::CLONE_NEWNET;
+        }
+        if self.mount {
+            flags |= CloneFlags::CLONE_NEWNS;
+        }
+        if self.ipc {
+            flags |= CloneFlags::CLONE_NEWIPC;
+        }
+        if self.uts {
+            flags |= CloneFlags::CLONE_NEWUTS;
+        }
+        flags
+    }
+
+    /// Move the calling (forked) process into new namespaces.
+    ///
+    /// A new pid namespace only applies to the children of the caller, so more processes are forked:
+    /// - the caller stays outside to wait, and exits in the same way as the program;
+    /// - its child becomes the init (pid 1) of the namespace, which reaps orphans
+    ///   and reports the wait status of the program through a pipe;
+    /// - the grandchild returns from this function to run the program.
+    ///
+    /// The program is not made the init since the init ignores signals without a handler,
+    /// including `SIGXCPU` and `SIGXFSZ` raised by rlimits.
+    /// When the init exits, every process left in the namespace is killed by the kernel.
+    ///
+    /// In rootless mode (`root_id_maps` from `RootlessIdMaps`) a user namespace is created
+    /// as well, where the caller becomes root (mapped to its own ids outside)
+    /// to own the other namespaces.
+    ///
+    /// `spawn_channel` is closed in the processes forked to wait,
+    /// which never run the program, see `spawn::create_spawn_channel()`.
+    pub(crate) fn enter(
+        &self,
+        root_id_maps: Option<&IdMaps>,
+        spawn_channel: Option<RawFd>,
+    ) -> Result<(), Errno> {
+        if let Some(root_id_maps) = root_id_maps {
+            let proc_dir = open_proc_dir()?;
+            unshare(self.get_clone_flags() | CloneFlags::CLONE_NEWUSER)?;
+            write_id_maps(&proc_dir, root_id_maps)?;
+        } else {
+            unshare(self.get_clone_flags())?;
+        }
+
+        if self.pid {
+            let (status_read, status_write) = pipe()?;
+            match unsafe { fork() }? {
+                ForkResult::Parent { child } => {
+                    drop(status_write);
+                    close_spawn_channel(spawn_channel);
+                    let init_status = wait_status(child.as_raw(), 0);
+                    let mut buf = [0u8; 4];
+                    let status = match read(status_read.as_raw_fd(), &mut buf) {
+                        Ok(4) => i32::from_ne_bytes(buf),
+                        _ => init_status,
+                    };
+                    forward_wait_status(status)
+                }
+                ForkResult::Child => drop(status_read),
+            }
+            match unsafe { fork() }? {
+                ForkResult::Parent { child } => {
+                    close_spawn_channel(spawn_channel);
+                    let status = wait_status(child.as_raw(), libc::__WALL);
+                    let _ = write(&status_write, &status.to_ne_bytes());
+                    unsafe { libc::_exit(0) }
+                }
+                ForkResult::Child => drop(status_write),
+            }
+        }
+
+        if self.mount {
+            // Stop propagating mount events to the host
+            mount(
+                None::<&str>,
+                "/",
+                None::<&str>,
+                MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+                None::<&str>,
+            )?;
+            if self.pid {
+                mount(
+                    Some("proc"),
+                    "/proc",
+                    Some("proc"),
+                    MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+                    None::<&str>,
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether namespaces can be created without root by creating a user namespace first.
+///
+/// Some systems disable unprivileged user namespaces, or (like AppArmor on Ubuntu)
+/// allow creating them without granting any capability, so mounting is tried in a forked process.
+pub fn is_rootless_supported() -> bool {
+    static ROOTLESS_SUPPORTED: OnceLock<bool> = OnceLock::new();
+    *ROOTLESS_SUPPORTED.get_or_init(|| {
+        let id_maps = RootlessIdMaps::new();
+        let supported = match unsafe { fork() } {
+            Ok(ForkResult::Parent { child }) => {
+                let mut status: libc::c_int = 0;
+                unsafe { libc::waitpid(child.as_raw(), &mut status, 0) };
+                libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0
+            }
+            Ok(ForkResult::Child) => {
+                let configs = NamespaceConfigs {
+                    mount: true,
+                    ..Default::default()
+                };
+                let code = if configs.enter(Some(&id_maps.root), None).is_ok() {
+                    0
+                } else {
+                    1
+                };
+                unsafe { libc::_exit(code) }
+            }
+            Err(_) => false,
+        };
+        log::info!("Rootless sandbox supported: {}", supported);
+        supported
+    })
+}
+
+/// The lines of `uid_map` and `gid_map` mapping a single id of a user namespace
+/// to its parent one, formatted before `fork()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct IdMaps {
+    uid_map: String,
+    gid_map: String,
+}
+
+impl IdMaps {
+    fn new(inside_uid: u32, outside_uid: u32, inside_gid: u32, outside_gid: u32) -> Self {
+        Self {
+            uid_map: format!("{} {} 1", inside_uid, outside_uid),
+            gid_map: format!("{} {} 1", inside_gid, outside_gid),
+        }
+    }
+}
+
+/// The id maps of rootless mode, where the program keeps the ids of the judger.
+#[derive(Debug, Clone)]
+pub(crate) struct RootlessIdMaps {
+    /// The caller becomes root of the user namespace, see `NamespaceConfigs::enter()`
+    pub(crate) root: IdMaps,
+    /// Back to the ids of the judger in a nested one, see `drop_to_mapped_user()`
+    pub(crate) user: IdMaps,
+}
+
+impl RootlessIdMaps {
+    pub(crate) fn new() -> Self {
+        let (uid, gid) = (getuid().as_raw(), getgid().as_raw());
+        Self {
+            root: IdMaps::new(0, uid, 0, gid),
+            user: IdMaps::new(uid, 0, gid, 0),
+        }
+    }
+}
+
+/// Open `/proc` before entering the jail, which may not have it,
+/// for `drop_to_mapped_user()` to write the id maps.
+pub(crate) fn open_proc_dir() -> Result<OwnedFd, Errno> {
+    let fd = open(
+        "/proc",
+        OFlag::O_PATH | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )?;
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// The rootless counterpart of `setuid()` and `setgid()`, after which the caller
+/// is no longer root in any namespace.
+///
+/// Only the caller's own ids can be mapped without privileges, so a nested user namespace
+/// is created with the ids of `user_id_maps` mapped to root of the outer one.
+/// The capabilities in the nested namespace are dropped on `execve()` as the caller is not root,
+/// and the outer namespaces (like the mount namespace of the jail) are not owned by it anyway.
+pub(crate) fn drop_to_mapped_user(proc_dir: &OwnedFd, user_id_maps: &IdMaps) -> Result<(), Errno> {
+    unshare(CloneFlags::CLONE_NEWUSER)?;
+    write_id_maps(proc_dir, user_id_maps)
+}
+
+/// Map the ids of the caller's user namespace to the parent one.
+/// `setgroups()` is denied as required for writing `gid_map`.
+fn write_id_maps(proc_dir: &OwnedFd, id_maps: &IdMaps) -> Result<(), Errno> {
+    write_proc_file(proc_dir, "self/setgroups", "deny")?;
+    write_proc_file(proc_dir, "self/uid_map", &id_maps.uid_map)?;
+    write_proc_file(proc_dir, "self/gid_map", &id_maps.gid_map)
+}
+
+fn write_proc_file(proc_dir: &OwnedFd, name: &str, content: &str) -> Result<(), Errno> {
+    let fd = openat(
+        Some(proc_dir.as_raw_fd()),
+        name,
+        OFlag::O_WRONLY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )?;
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    let written = write(&fd, content.as_bytes())?;
+    if written != content.len() {
+        return Err(Errno::EIO);
+    }
+    Ok(())
+}
+
+fn close_spawn_channel(spawn_channel: Option<RawFd>) {
+    if let Some(spawn_channel) = spawn_channel {
+        unsafe { libc::close(spawn_channel) };
+    }
+}
+
+/// Wait until `pid` terminates and get its raw wait status.
+/// Other children are reaped in the meantime, which is the duty of an init process.
+fn wait_status(pid: libc::pid_t, flags: libc::c_int) -> libc::c_int {
+    loop {
+        let mut status: libc::c_int = 0;
+        let res = unsafe { libc::waitpid(-1, &mut status, flags) };
+        if res == pid {
+            return status;
+        }
+        if res < 0 && Errno::last() != Errno::EINTR {
+            unsafe { libc::_exit(1) }
+        }
+    }
+}
+
+/// Exit with the exit code of a raw wait status, or get killed by its terminating signal.
+fn forward_wait_status(status: libc::c_int) -> ! {
+    if libc::WIFSIGNALED(status) {
+        let sig = libc::WTERMSIG(status);
+        unsafe {
+            // Avoid dumping core for the forwarded signal
+            let rlim = libc::rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+            libc::setrlimit(libc::RLIMIT_CORE, &rlim);
+            libc::signal(sig, libc::SIG_DFL);
+            let mut set: libc::sigset_t = std::mem::zeroed();
+            libc::sigemptyset(&mut set);
+            libc::sigaddset(&mut set, sig);
+            libc::sigprocmask(libc::SIG_UNBLOCK, &set, std::ptr::null_mut());
+            libc::kill(libc::getpid(), sig);
+            libc::_exit(128 + sig)
+        }
+    }
+    if libc::WIFEXITED(status) {
+        unsafe { libc::_exit(libc::WEXITSTATUS(status)) }
+    }
+    unsafe { libc::_exit(1) }
+}
+
+#[test]
+fn test_get_clone_flags() {
+    assert!(!NamespaceConfigs::default().is_enabled());
+    assert_eq!(
+        NamespaceConfigs::default().get_clone_flags(),
+        CloneFlags::empty()
+    );
+    let configs = NamespaceConfigs {
+        pid: true,
+        net: true,
+        ..Default::default()
+    };
+    assert!(configs.is_enabled());
+    assert_eq!(
+        configs.get_clone_flags(),
+        CloneFlags::CLONE_NEWPID | CloneFlags::CLONE_NEWNET
+    );
+}
+
+#[test]
+fn test_rootless_id_maps() {
+    let (uid, gid) = (getuid().as_raw(), getgid().as_raw());
+    let id_maps = RootlessIdMaps::new();
+    assert_eq!(id_maps.root.uid_map, format!("0 {} 1", uid));
+    assert_eq!(id_maps.root.gid_map, format!("0 {} 1", gid));
+    assert_eq!(id_maps.user.uid_map, format!("{} 0 1", uid));
+    assert_eq!(id_maps.user.gid_map, format!("{} 0 1", gid));
+}
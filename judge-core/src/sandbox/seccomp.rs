@@ -0,0 +1,405 @@
+use anyhow::anyhow;
+use libseccomp::{
+    check_api, ScmpAction, ScmpArgCompare, ScmpCompareOp, ScmpFilterContext, ScmpSyscall,
+    ScmpVersion,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::compiler::Language;
+use crate::error::JudgeCoreError;
+
+static BUILTIN_SECCOMP_PROFILES: &str = include_str!("seccomp_profiles.yaml");
+/// Avoid looping forever on profiles extending each other
+const MAX_EXTENDS_DEPTH: usize = 8;
+
+static SECCOMP_PROFILES: OnceLock<SeccompProfiles> = OnceLock::new();
+
+/// At most this many distinct argument lists are kept for each learned syscall
+const MAX_LEARNED_ARGS: usize = 8;
+
+/// Used by creating, running and exiting threads besides `clone()`
+const THREAD_SYSCALLS: [&str; 11] = [
+    "exit",
+    "futex",
+    "set_robust_list",
+    "rseq",
+    "gettid",
+    "mmap",
+    "mprotect",
+    "munmap",
+    "madvise",
+    "rt_sigprocmask",
+    "sched_yield",
+];
+
+/// Replace the built-in seccomp profiles with the ones defined in `path`.
+/// Should be called before any profile is used.
+pub fn init_seccomp_profiles(path: &Path) -> Result<(), JudgeCoreError> {
+    let profiles = SeccompProfiles::load(path)?;
+    SECCOMP_PROFILES
+        .set(profiles)
+        .map_err(|_| JudgeCoreError::AnyhowError(anyhow!("Seccomp profiles are already in use")))
+}
+
+/// Get the seccomp profiles loaded by `init_seccomp_profiles()`, or the built-in ones.
+pub fn get_seccomp_profiles() -> &'static SeccompProfiles {
+    SECCOMP_PROFILES.get_or_init(|| {
+        SeccompProfiles::from_yaml(BUILTIN_SECCOMP_PROFILES)
+            .expect("Built-in seccomp profiles should be valid")
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeccompAction {
+    Allow,
+    KillProcess,
+    KillThread,
+    Trap,
+    Log,
+    /// Fail the syscall with the `errno` of the rule
+    Errno,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeccompCompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// Compare `arg & mask` with `value`
+    MaskedEq,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SeccompArgRule {
+    /// Index of the syscall argument, starting from 0
+    pub index: u32,
+    pub op: SeccompCompareOp,
+    pub value: u64,
+    #[serde(default)]
+    pub mask: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SeccompRule {
+    pub syscall: String,
+    #[serde(default = "default_rule_action")]
+    pub action: SeccompAction,
+    /// Only used by `SeccompAction::Errno`, `EPERM` when not set
+    #[serde(default)]
+    pub errno: Option<i32>,
+    /// The rule only matches when all the arguments match
+    #[serde(default)]
+    pub args: Vec<SeccompArgRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SeccompProfile {
+    pub default_action: SeccompAction,
+    /// Name of another profile whose rules are included before the rules of this one
+    #[serde(default)]
+    pub extends: Option<String>,
+    #[serde(default)]
+    pub rules: Vec<SeccompRule>,
+}
+
+/// Named seccomp profiles, with the default profile name of each language.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SeccompProfiles {
+    pub profiles: HashMap<String, SeccompProfile>,
+    #[serde(default)]
+    pub languages: HashMap<Language, String>,
+}
+
+impl SeccompProfiles {
+    pub fn from_yaml(content: &str) -> Result<Self, JudgeCoreError> {
+        serde_yaml::from_str(content).map_err(|e| JudgeCoreError::AnyhowError(e.into()))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, JudgeCoreError> {
+        log::info!("Loading seccomp profiles from {:?}", path);
+        Self::from_yaml(&fs::read_to_string(path)?)
+    }
+
+    /// Get the profile named `name`, with the rules of extended profiles included.
+    pub fn resolve(&self, name: &str) -> Result<SeccompProfile, JudgeCoreError> {
+        let mut profile = self.get(name)?.clone();
+        let mut extends = profile.extends.take();
+        let mut depth = 0;
+        while let Some(base_name) = extends {
+            depth += 1;
+            if depth > MAX_EXTENDS_DEPTH {
+                return Err(JudgeCoreError::AnyhowError(anyhow!(
+                    "Seccomp profile {} extends too deep",
+                    name
+                )));
+            }
+            let base = self.get(&base_name)?;
+            let mut rules = base.rules.clone();
+            rules.append(&mut profile.rules);
+            profile.rules = rules;
+            extends = base.extends.clone();
+        }
+        Ok(profile)
+    }
+
+    pub fn resolve_for_language(
+        &self,
+        language: Language,
+    ) -> Result<SeccompProfile, JudgeCoreError> {
+        let name = self.languages.get(&language).ok_or_else(|| {
+            JudgeCoreError::AnyhowError(anyhow!("No seccomp profile for language {}", language))
+        })?;
+        self.resolve(name)
+    }
+
+    fn get(&self, name: &str) -> Result<&SeccompProfile, JudgeCoreError> {
+        self.profiles.get(name).ok_or_else(|| {
+            JudgeCoreError::AnyhowError(anyhow!("Seccomp profile not found: {}", name))
+        })
+    }
+}
+
+impl SeccompProfile {
+    /// Allow the syscalls needed by threads, in case the profile does not mention them.
+    ///
+    /// `clone()` is only allowed with `CLONE_THREAD`, so processes still can not be created.
+    /// `clone3()` takes its flags in a struct which seccomp can not inspect,
+    /// so it fails with `ENOSYS` and libc falls back to `clone()`.
+    pub fn allow_threads(&mut self) {
+        let clone_thread = libc::CLONE_THREAD as u64;
+        let mut thread_rules = vec![
+            SeccompRule {
+                syscall: "clone".to_string(),
+                action: SeccompAction::Allow,
+                errno: None,
+                args: vec![SeccompArgRule {
+                    index: 0,
+                    op: SeccompCompareOp::MaskedEq,
+                    value: clone_thread,
+                    mask: clone_thread,
+                }],
+            },
+            SeccompRule {
+                syscall: "clone3".to_string(),
+                action: SeccompAction::Errno,
+                errno: Some(libc::ENOSYS),
+                args: vec![],
+            },
+        ];
+        for syscall in THREAD_SYSCALLS.iter() {
+            thread_rules.push(SeccompRule {
+                syscall: syscall.to_string(),
+                action: SeccompAction::Allow,
+                errno: None,
+                args: vec![],
+            });
+        }
+        thread_rules.retain(|rule| {
+            !self
+                .rules
+                .iter()
+                .any(|existing| existing.syscall == rule.syscall)
+        });
+        self.rules.append(&mut thread_rules);
+    }
+
+    /// Only the rules of this profile are used, see `SeccompProfiles::resolve()`.
+    ///
+    /// When seccomp user notification is supported, `kill_process` becomes a notification,
+    /// so that the sandbox can tell which syscall is restricted before killing the process.
+    pub fn build_filter(&self) -> Result<ScmpFilterContext, JudgeCoreError> {
+        let default_action = to_scmp_action(self.default_action, None);
+        let mut scmp_filter = ScmpFilterContext::new_filter(default_action)?;
+        for rule in self.rules.iter() {
+            let action = to_scmp_action(rule.action, rule.errno);
+            // libseccomp rejects rules doing the same as the default action
+            if action == default_action {
+                continue;
+            }
+            let syscall = ScmpSyscall::from_name(&rule.syscall)?;
+            let comparators = rule
+                .args
+                .iter()
+                .map(|arg| ScmpArgCompare::new(arg.index, to_scmp_compare_op(arg), arg.value))
+                .collect::<Vec<_>>();
+            scmp_filter.add_rule_conditional_exact(action, syscall, &comparators)?;
+        }
+        Ok(scmp_filter)
+    }
+
+    /// A whitelist of the learned syscalls, killing the process on anything else.
+    /// The arguments are not restricted, see `format_learned_profile()` for tightening them.
+    pub fn from_learned_syscalls(learned_syscalls: &[LearnedSyscall]) -> Self {
+        Self {
+            default_action: SeccompAction::KillProcess,
+            extends: None,
+            rules: learned_syscalls
+                .iter()
+                .map(|learned_syscall| SeccompRule {
+                    syscall: learned_syscall.name.clone(),
+                    action: SeccompAction::Allow,
+                    errno: None,
+                    args: vec![],
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A syscall seen in learning mode, see `Sandbox::enable_syscall_learning()`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LearnedSyscall {
+    pub name: String,
+    pub count: u64,
+    /// Distinct arguments of the first calls, at most `MAX_LEARNED_ARGS` of them
+    pub args: Vec<[u64; 6]>,
+}
+
+impl LearnedSyscall {
+    pub(crate) fn record(
+        learned_syscalls: &mut BTreeMap<String, LearnedSyscall>,
+        name: String,
+        args: [u64; 6],
+    ) {
+        let learned_syscall =
+            learned_syscalls
+                .entry(name.clone())
+                .or_insert_with(|| LearnedSyscall {
+                    name,
+                    count: 0,
+                    args: vec![],
+                });
+        learned_syscall.count += 1;
+        if learned_syscall.args.len() < MAX_LEARNED_ARGS && !learned_syscall.args.contains(&args) {
+            learned_syscall.args.push(args);
+        }
+    }
+}
+
+/// Every syscall is reported to the supervisor, which lets it continue.
+pub(crate) fn build_learning_filter() -> Result<ScmpFilterContext, JudgeCoreError> {
+    if !is_notify_supported() {
+        return Err(JudgeCoreError::AnyhowError(anyhow!(
+            "Learning syscalls requires seccomp user notification"
+        )));
+    }
+    Ok(ScmpFilterContext::new_filter(ScmpAction::Notify)?)
+}
+
+/// A profiles file (see `SeccompProfiles::from_yaml()`) with the learned profile named `name`.
+/// Each rule is commented with how it was called, for restricting the arguments by hand.
+pub fn format_learned_profile(name: &str, learned_syscalls: &[LearnedSyscall]) -> String {
+    let mut yaml = String::new();
+    let _ = writeln!(yaml, "# Learned from a reference run, review before use");
+    let _ = writeln!(yaml, "profiles:");
+    let _ = writeln!(yaml, "  {}:", name);
+    let _ = writeln!(yaml, "    default_action: kill_process");
+    let _ = writeln!(yaml, "    rules:");
+    for learned_syscall in learned_syscalls {
+        let _ = writeln!(yaml, "      # {} calls, e.g.", learned_syscall.count);
+        for args in learned_syscall.args.iter() {
+            let args = args
+                .iter()
+                .map(|arg| format!("{:#x}", arg))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let _ = writeln!(yaml, "      #   ({})", args);
+        }
+        let _ = writeln!(yaml, "      - syscall: {}", learned_syscall.name);
+    }
+    yaml
+}
+
+fn default_rule_action() -> SeccompAction {
+    SeccompAction::Allow
+}
+
+fn to_scmp_action(action: SeccompAction, errno: Option<i32>) -> ScmpAction {
+    match action {
+        SeccompAction::Allow => ScmpAction::Allow,
+        SeccompAction::KillProcess if is_notify_supported() => ScmpAction::Notify,
+        SeccompAction::KillProcess => ScmpAction::KillProcess,
+        SeccompAction::KillThread => ScmpAction::KillThread,
+        SeccompAction::Trap => ScmpAction::Trap,
+        SeccompAction::Log => ScmpAction::Log,
+        SeccompAction::Errno => ScmpAction::Errno(errno.unwrap_or(libc::EPERM)),
+    }
+}
+
+/// Seccomp user notification needs libseccomp 2.5.0 and Linux 5.0 (API level 6).
+pub fn is_notify_supported() -> bool {
+    static NOTIFY_SUPPORTED: OnceLock<bool> = OnceLock::new();
+    *NOTIFY_SUPPORTED.get_or_init(|| check_api(6, ScmpVersion::from((2, 5, 0))).unwrap_or(false))
+}
+
+fn to_scmp_compare_op(arg: &SeccompArgRule) -> ScmpCompareOp {
+    match arg.op {
+        SeccompCompareOp::Eq => ScmpCompareOp::Equal,
+        SeccompCompareOp::Ne => ScmpCompareOp::NotEqual,
+        SeccompCompareOp::Lt => ScmpCompareOp::Less,
+        SeccompCompareOp::Le => ScmpCompareOp::LessOrEqual,
+        SeccompCompareOp::Gt => ScmpCompareOp::Greater,
+        SeccompCompareOp::Ge => ScmpCompareOp::GreaterEqual,
+        SeccompCompareOp::MaskedEq => ScmpCompareOp::MaskedEqual(arg.mask),
+    }
+}
+
+#[test]
+fn test_builtin_seccomp_profiles() {
+    let profiles = SeccompProfiles::from_yaml(BUILTIN_SECCOMP_PROFILES).unwrap();
+    for language in [Language::Cpp, Language::Rust, Language::Python] {
+        let profile = profiles.resolve_for_language(language).unwrap();
+        assert!(profile.extends.is_none());
+        assert!(profile.rules.iter().any(|rule| rule.syscall == "execve"));
+        assert!(profile.rules.iter().any(|rule| rule.syscall == "openat"));
+    }
+    // Rules of `base` come through `cpp`
+    let rust = profiles.resolve("rust").unwrap();
+    assert_eq!(rust.rules[0].syscall, "read");
+    assert!(profiles.resolve("not_exist").is_err());
+}
+
+#[test]
+fn test_seccomp_profiles_extends_loop() {
+    let profiles = SeccompProfiles::from_yaml(
+        "profiles:\n  a:\n    extends: b\n    default_action: allow\n  b:\n    extends: a\n    default_action: allow\n",
+    )
+    .unwrap();
+    assert!(profiles.resolve("a").is_err());
+}
+
+#[test]
+fn test_format_learned_profile() {
+    let mut learned_syscalls = BTreeMap::new();
+    for fd in [0, 1, 1] {
+        LearnedSyscall::record(
+            &mut learned_syscalls,
+            "write".to_string(),
+            [fd, 0, 0, 0, 0, 0],
+        );
+    }
+    LearnedSyscall::record(&mut learned_syscalls, "read".to_string(), [0; 6]);
+    let learned_syscalls = learned_syscalls.into_values().collect::<Vec<_>>();
+    assert_eq!(learned_syscalls[1].count, 3);
+    assert_eq!(learned_syscalls[1].args.len(), 2);
+
+    let yaml = format_learned_profile("learned", &learned_syscalls);
+    let profile = SeccompProfiles::from_yaml(&yaml)
+        .unwrap()
+        .resolve("learned")
+        .unwrap();
+    assert_eq!(
+        profile,
+        SeccompProfile::from_learned_syscalls(&learned_syscalls)
+    );
+}
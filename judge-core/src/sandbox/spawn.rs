@@ -0,0 +1,264 @@
+use crate::error::JudgeCoreError;
+use nix::errno::Errno;
+use nix::sys::socket::{
+    recvmsg, setsockopt, socketpair, sockopt, AddressFamily, ControlMessageOwned, MsgFlags,
+    SockFlag, SockType,
+};
+use std::fmt;
+use std::io::IoSliceMut;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+/// Where the forked process failed before running the program,
+/// see `JudgeCoreError::SpawnFailed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SpawnStage {
+    SetProcessGroup = 1,
+    Redirect,
+    PinCpu,
+    SetSchedPolicy,
+    OpenProc,
+    EnterNamespaces,
+    ReportPid,
+    JoinCgroup,
+    SetHostname,
+    EnterJail,
+    DropPrivileges,
+    ChangeDirectory,
+    DisableRandomization,
+    LoadRlimits,
+    LoadSeccomp,
+    SendNotifyFd,
+    Exec,
+}
+
+impl SpawnStage {
+    const ALL: [Self; 17] = [
+        Self::SetProcessGroup,
+        Self::Redirect,
+        Self::PinCpu,
+        Self::SetSchedPolicy,
+        Self::OpenProc,
+        Self::EnterNamespaces,
+        Self::ReportPid,
+        Self::JoinCgroup,
+        Self::SetHostname,
+        Self::EnterJail,
+        Self::DropPrivileges,
+        Self::ChangeDirectory,
+        Self::DisableRandomization,
+        Self::LoadRlimits,
+        Self::LoadSeccomp,
+        Self::SendNotifyFd,
+        Self::Exec,
+    ];
+
+    fn from_u8(code: u8) -> Option<Self> {
+        Self::ALL.iter().copied().find(|stage| *stage as u8 == code)
+    }
+}
+
+impl fmt::Display for SpawnStage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stage = match self {
+            Self::SetProcessGroup => "set the process group",
+            Self::Redirect => "redirect stdio",
+            Self::PinCpu => "pin to the leased core",
+            Self::SetSchedPolicy => "set the scheduling policy",
+            Self::OpenProc => "open /proc",
+            Self::EnterNamespaces => "enter namespaces",
+            Self::ReportPid => "report the pid",
+            Self::JoinCgroup => "join the cgroup",
+            Self::SetHostname => "set the hostname",
+            Self::EnterJail => "enter the jail",
+            Self::DropPrivileges => "drop privileges",
+            Self::ChangeDirectory => "change the working directory",
+            Self::DisableRandomization => "disable address space randomization",
+            Self::LoadRlimits => "load rlimit configs",
+            Self::LoadSeccomp => "load the seccomp filter",
+            Self::SendNotifyFd => "send the seccomp notify fd",
+            Self::Exec => "execute the program",
+        };
+        write!(f, "{}", stage)
+    }
+}
+
+// The first byte of a message, followed by the errno for failures
+const NOTIFY_FD_MESSAGE: u8 = 0;
+// Out of the range of `SpawnStage`
+const PID_MESSAGE: u8 = u8::MAX;
+const MESSAGE_LEN: usize = 1 + std::mem::size_of::<i32>();
+
+/// What the forked process reported before running the program, see `receive_spawn_report()`.
+#[derive(Debug, Default)]
+pub(crate) struct SpawnReport {
+    pub notify_fd: Option<OwnedFd>,
+    /// The pid of the program in the pid namespace of the supervisor,
+    /// only reported when the program runs in a new pid namespace
+    pub program_pid: Option<i32>,
+}
+
+/// A socket pair for the forked process to report to `Sandbox::spawn()`:
+/// the seccomp notify fd, which is only available after loading the filter,
+/// the pid of the program, or the stage where it failed.
+/// The end of the forked process is closed on `execve()`,
+/// so the end of the channel means the program is running.
+/// The end of the supervisor receives the credentials of the sender with every message.
+pub(crate) fn create_spawn_channel() -> Result<(OwnedFd, OwnedFd), JudgeCoreError> {
+    let (supervisor_end, child_end) = socketpair(
+        AddressFamily::Unix,
+        SockType::SeqPacket,
+        None,
+        SockFlag::SOCK_CLOEXEC,
+    )?;
+    setsockopt(&supervisor_end, sockopt::PassCred, &true)?;
+    Ok((supervisor_end, child_end))
+}
+
+/// Called by the forked process after loading the seccomp filter,
+/// `sendmsg()` on `channel` should be allowed by the filter.
+/// Only raw syscalls are used.
+pub(crate) fn send_notify_fd(channel: RawFd, notify_fd: RawFd) -> Result<(), Errno> {
+    send_message(channel, NOTIFY_FD_MESSAGE, 0, Some(notify_fd))
+}
+
+/// Called by the forked process which runs the program in a new pid namespace,
+/// where `getpid()` means nothing to the supervisor.
+/// The kernel translates the pid in the credentials of the message instead.
+pub(crate) fn send_pid(channel: RawFd) -> Result<(), Errno> {
+    send_message(channel, PID_MESSAGE, 0, None)
+}
+
+/// Report the failed stage with `errno` and exit, called by the forked process.
+pub(crate) fn fail_spawn(channel: RawFd, stage: SpawnStage, errno: Errno) -> ! {
+    // Nothing else to do if the supervisor is gone, it sees the exit anyway
+    let _ = send_message(channel, stage as u8, errno as i32, None);
+    unsafe { libc::_exit(1) }
+}
+
+/// Unwrap the result of a stage in the forked process, or `fail_spawn()`.
+pub(crate) fn check_spawn<T>(channel: RawFd, stage: SpawnStage, result: Result<T, Errno>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(errno) => fail_spawn(channel, stage, errno),
+    }
+}
+
+fn send_message(channel: RawFd, kind: u8, errno: i32, fd: Option<RawFd>) -> Result<(), Errno> {
+    let mut payload = [0u8; MESSAGE_LEN];
+    payload[0] = kind;
+    payload[1..].copy_from_slice(&errno.to_ne_bytes());
+    let mut iov = libc::iovec {
+        iov_base: payload.as_mut_ptr().cast(),
+        iov_len: payload.len(),
+    };
+    // Large enough and aligned for one fd, without allocating like `nix::sys::socket::sendmsg()`
+    let mut cmsg_buffer = [0u64; 4];
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    if let Some(fd) = fd {
+        unsafe {
+            let fd_len = std::mem::size_of::<RawFd>() as u32;
+            msg.msg_control = cmsg_buffer.as_mut_ptr().cast();
+            msg.msg_controllen = libc::CMSG_SPACE(fd_len) as _;
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(fd_len) as _;
+            std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut RawFd, fd);
+        }
+    }
+    loop {
+        let sent = unsafe { libc::sendmsg(channel, &msg, 0) };
+        match sent {
+            _ if sent == payload.len() as isize => return Ok(()),
+            -1 if Errno::last() == Errno::EINTR => continue,
+            -1 => return Err(Errno::last()),
+            _ => return Err(Errno::EIO),
+        }
+    }
+}
+
+/// Block until the forked process runs the program, i.e. the channel is closed,
+/// returning what it sent.
+/// The failure reported by the forked process is returned as `JudgeCoreError::SpawnFailed`.
+pub(crate) fn receive_spawn_report(channel: &OwnedFd) -> Result<SpawnReport, JudgeCoreError> {
+    let mut report = SpawnReport::default();
+    loop {
+        let mut buf = [0u8; MESSAGE_LEN];
+        let mut iov = [IoSliceMut::new(&mut buf)];
+        let mut cmsg_buffer = nix::cmsg_space!([RawFd; 1], libc::ucred);
+        let msg = match recvmsg::<()>(
+            channel.as_raw_fd(),
+            &mut iov,
+            Some(&mut cmsg_buffer),
+            MsgFlags::MSG_CMSG_CLOEXEC,
+        ) {
+            Ok(msg) => msg,
+            Err(Errno::EINTR) => continue,
+            Err(e) => return Err(JudgeCoreError::NixErrno(e)),
+        };
+        if msg.bytes == 0 {
+            return Ok(report);
+        }
+        let mut sender_pid = None;
+        for cmsg in msg.cmsgs()? {
+            match cmsg {
+                ControlMessageOwned::ScmRights(fds) => {
+                    for fd in fds {
+                        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+                        report.notify_fd.get_or_insert(fd);
+                    }
+                }
+                ControlMessageOwned::ScmCredentials(credentials) => {
+                    sender_pid = Some(credentials.pid())
+                }
+                _ => {}
+            }
+        }
+        if msg.bytes != MESSAGE_LEN || buf[0] == NOTIFY_FD_MESSAGE {
+            continue;
+        }
+        if buf[0] == PID_MESSAGE {
+            report.program_pid = sender_pid;
+            continue;
+        }
+        let mut errno = [0u8; MESSAGE_LEN - 1];
+        errno.copy_from_slice(&buf[1..]);
+        let errno = Errno::from_raw(i32::from_ne_bytes(errno));
+        return match SpawnStage::from_u8(buf[0]) {
+            Some(stage) => Err(JudgeCoreError::SpawnFailed(stage, errno)),
+            None => Err(JudgeCoreError::NixErrno(errno)),
+        };
+    }
+}
+
+#[test]
+fn test_spawn_channel() {
+    let (supervisor_end, child_end) = create_spawn_channel().unwrap();
+    let notify_fd = std::fs::File::open("/dev/null").unwrap();
+    send_notify_fd(child_end.as_raw_fd(), notify_fd.as_raw_fd()).unwrap();
+    send_pid(child_end.as_raw_fd()).unwrap();
+    drop(child_end);
+    let report = receive_spawn_report(&supervisor_end).unwrap();
+    assert!(report.notify_fd.is_some());
+    assert_eq!(report.program_pid, Some(std::process::id() as i32));
+
+    let (supervisor_end, child_end) = create_spawn_channel().unwrap();
+    send_message(
+        child_end.as_raw_fd(),
+        SpawnStage::EnterJail as u8,
+        Errno::EPERM as i32,
+        None,
+    )
+    .unwrap();
+    drop(child_end);
+    match receive_spawn_report(&supervisor_end) {
+        Err(JudgeCoreError::SpawnFailed(stage, errno)) => {
+            assert_eq!(stage, SpawnStage::EnterJail);
+            assert_eq!(errno, Errno::EPERM);
+        }
+        result => panic!("Unexpected spawn report: {:?}", result),
+    }
+}
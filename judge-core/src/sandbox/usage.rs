@@ -0,0 +1,65 @@
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::time::Duration;
+
+use crate::error::JudgeCoreError;
+
+/// Resource usage of a running sandbox at some point, taken by `Sandbox::wait()`
+/// every `Sandbox::set_usage_sample_interval()`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageSample {
+    /// Since the sandbox was created
+    pub elapsed: Duration,
+    /// Resident memory in bytes (charged memory of the cgroup when available)
+    pub memory_bytes: u64,
+    /// CPU time used so far
+    pub cpu_time: Duration,
+}
+
+/// Usage of a single process read from `/proc/<pid>/status` and `/proc/<pid>/stat`,
+/// which does not cover its descendants.
+pub(crate) fn read_proc_usage(pid: i32) -> Result<(u64, Duration), JudgeCoreError> {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid))?;
+    // Missing for zombies
+    let memory_bytes = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|value| {
+            value
+                .trim()
+                .trim_end_matches("kB")
+                .trim()
+                .parse::<u64>()
+                .ok()
+        })
+        .ok_or_else(|| JudgeCoreError::AnyhowError(anyhow::anyhow!("VmRSS is not available")))?
+        * 1024;
+
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid))?;
+    Ok((memory_bytes, parse_stat_cpu_time(&stat)?))
+}
+
+/// `utime` and `stime` are the 14th and 15th fields, in clock ticks.
+/// The fields are counted from the end of `comm`, which may contain spaces and parentheses.
+fn parse_stat_cpu_time(stat: &str) -> Result<Duration, JudgeCoreError> {
+    let invalid = || JudgeCoreError::AnyhowError(anyhow::anyhow!("Invalid stat: {}", stat));
+    let (_, fields) = stat.rsplit_once(')').ok_or_else(invalid)?;
+    let fields = fields.split_whitespace().collect::<Vec<_>>();
+    let mut ticks = 0;
+    for field in fields.get(11..13).ok_or_else(invalid)? {
+        ticks += field.parse::<u64>().map_err(|_| invalid())?;
+    }
+    let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64;
+    Ok(Duration::from_micros(ticks * 1_000_000 / ticks_per_second))
+}
+
+#[test]
+fn test_parse_stat_cpu_time() {
+    let stat = "42 (a b) c) R 1 42 42 0 -1 4194304 100 0 0 0 150 50 0 0 20 0 1 0 1 2 3";
+    let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as u32;
+    assert_eq!(
+        parse_stat_cpu_time(stat).unwrap(),
+        Duration::from_secs(200) / ticks_per_second
+    );
+    assert!(parse_stat_cpu_time("42 (a) R 1").is_err());
+}
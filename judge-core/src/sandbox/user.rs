@@ -0,0 +1,96 @@
+use anyhow::anyhow;
+use nix::unistd::{getuid, User};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use super::lease::{Lease, LeasePool};
+use super::SANDBOX_USERNAME;
+use crate::error::JudgeCoreError;
+
+static SANDBOX_USER_POOL: OnceLock<SandboxUserPool> = OnceLock::new();
+
+/// Ids of the default pool, enough for interactive judging which runs two programs at once
+pub const DEFAULT_SANDBOX_ID_COUNT: u32 = 2;
+/// How long a run waits for the ids of another run to be released when the pool is exhausted
+pub const SANDBOX_USER_LEASE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Only root can switch to the sandbox users, so the pool is not used otherwise
+/// (e.g. in rootless mode, see `namespace::is_rootless_supported()`).
+pub fn is_root() -> bool {
+    getuid().is_root()
+}
+
+/// Replace the default pool (see `get_sandbox_user_pool()`) with `range`.
+/// Should be called before any sandbox is created.
+pub fn init_sandbox_user_pool(range: SandboxUserRange) -> Result<(), JudgeCoreError> {
+    SANDBOX_USER_POOL
+        .set(SandboxUserPool::from_range(range))
+        .map_err(|_| JudgeCoreError::AnyhowError(anyhow!("Sandbox user pool is already in use")))
+}
+
+/// Get the pool set by `init_sandbox_user_pool()`,
+/// or a pool of `DEFAULT_SANDBOX_ID_COUNT` ids starting from the `SANDBOX_USERNAME` account,
+/// which is resolved on first use.
+pub fn get_sandbox_user_pool() -> Result<&'static SandboxUserPool, JudgeCoreError> {
+    if let Some(pool) = SANDBOX_USER_POOL.get() {
+        return Ok(pool);
+    }
+    let range = SandboxUserRange::from_username(SANDBOX_USERNAME, DEFAULT_SANDBOX_ID_COUNT)?;
+    Ok(SANDBOX_USER_POOL.get_or_init(|| SandboxUserPool::from_range(range)))
+}
+
+/// `count` consecutive ids starting from `uid` and `gid`, the n-th run gets `(uid + n, gid + n)`.
+/// The ids do not need existing accounts, but should not be used by anything else.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SandboxUserRange {
+    pub uid: u32,
+    pub gid: u32,
+    pub count: u32,
+}
+
+impl SandboxUserRange {
+    /// Start the range from the ids of the account `username`, looked up with `getpwnam()`.
+    pub fn from_username(username: &str, count: u32) -> Result<Self, JudgeCoreError> {
+        let user = User::from_name(username)?.ok_or_else(|| {
+            JudgeCoreError::AnyhowError(anyhow!("Sandbox user {} not found", username))
+        })?;
+        log::debug!(
+            "Sandbox user {} uid={} gid={}, {} ids in the pool",
+            username,
+            user.uid,
+            user.gid,
+            count
+        );
+        Ok(Self {
+            uid: user.uid.as_raw(),
+            gid: user.gid.as_raw(),
+            count: count.max(1),
+        })
+    }
+}
+
+/// A uid/gid pair of the `SandboxUserPool`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SandboxUser {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Hands out a distinct uid/gid pair to each run, so that concurrent runs
+/// neither share per-user limits like `RLIMIT_NPROC` nor can signal or ptrace each other.
+/// The pairs are never shared, see `LeasePool::lease_exclusive()`.
+pub type SandboxUserPool = LeasePool<SandboxUser>;
+
+pub type SandboxUserLease<'a> = Lease<'a, SandboxUser>;
+
+impl SandboxUserPool {
+    pub fn from_range(range: SandboxUserRange) -> Self {
+        let users = (0..range.count.max(1))
+            .map(|n| SandboxUser {
+                uid: range.uid + n,
+                gid: range.gid + n,
+            })
+            .collect();
+        Self::new("Sandbox user pool", users)
+    }
+}
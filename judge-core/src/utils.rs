@@ -2,7 +2,7 @@ use anyhow::anyhow;
 use std::path::PathBuf;
 
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Read};
 
 use crate::error::JudgeCoreError;
 
@@ -26,3 +26,11 @@ pub fn get_pathbuf_str(path: &PathBuf) -> Result<String, JudgeCoreError> {
         ))),
     }
 }
+
+/// Read at most `limit` bytes from the beginning of the file,
+/// invalid UTF-8 (e.g. a multi-byte character cut at the end) is replaced.
+pub fn read_file_head(path: &PathBuf, limit: u64) -> Result<String, JudgeCoreError> {
+    let mut content = Vec::new();
+    File::open(path)?.take(limit).read_to_end(&mut content)?;
+    Ok(String::from_utf8_lossy(&content).into_owned())
+}
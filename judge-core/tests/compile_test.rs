@@ -1,6 +1,10 @@
 use std::path::PathBuf;
 
-use judge_core::compiler::{Compiler, Language};
+use judge_core::{
+    compiler::{Compiler, Language},
+    error::JudgeCoreError,
+    sandbox::cancellation::CancellationToken,
+};
 
 const TEST_DATA_PATH: &str = "tests/data";
 const TEST_TEMP_PATH: &str = "tests/temp";
@@ -38,3 +42,19 @@ fn test_compile_py() {
         Err(e) => panic!("{:?}", e),
     }
 }
+
+#[test]
+fn test_compile_cancelled() {
+    init();
+    let mut compiler = Compiler::new(Language::Cpp, vec![]);
+    let cancellation_token = CancellationToken::new().unwrap();
+    compiler.set_cancellation_token(Some(cancellation_token.clone()));
+    cancellation_token.cancel();
+    match compiler.compile(
+        &PathBuf::from(TEST_DATA_PATH).join("built-in-programs/src/programs/infinite_loop.cpp"),
+        &PathBuf::from(TEST_TEMP_PATH).join("infinite_loop_cancelled.o"),
+    ) {
+        Err(JudgeCoreError::Cancelled) => {}
+        result => panic!("Unexpected compile result: {:?}", result),
+    }
+}
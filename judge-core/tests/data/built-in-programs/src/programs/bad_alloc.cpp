@@ -0,0 +1,7 @@
+#include <new>
+
+int main() {
+    // Thrown directly, so that it does not depend on the memory limit
+    throw std::bad_alloc();
+    return 0;
+}
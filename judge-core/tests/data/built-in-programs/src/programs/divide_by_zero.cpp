@@ -0,0 +1,8 @@
+#include <stdio.h>
+
+int main() {
+    // volatile keeps the division from being optimized away
+    volatile int dividend = 10, zero = 0;
+    printf("%d\n", dividend / zero);
+    return 0;
+}
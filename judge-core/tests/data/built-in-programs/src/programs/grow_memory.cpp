@@ -0,0 +1,16 @@
+#include <string.h>
+#include <unistd.h>
+
+#include <vector>
+
+int main() {
+    // Allocate 1MB every 5ms, about 200ms in total
+    std::vector<char *> chunks;
+    for (int i = 0; i < 40; i++) {
+        char *chunk = new char[1024 * 1024];
+        memset(chunk, i, 1024 * 1024);
+        chunks.push_back(chunk);
+        usleep(5000);
+    }
+    return 0;
+}
@@ -0,0 +1,9 @@
+#include <linux/futex.h>
+#include <sys/syscall.h>
+#include <unistd.h>
+
+int main() {
+    // Wait on a futex which will never be woken up, just like a deadlock
+    int word = 0;
+    syscall(SYS_futex, &word, FUTEX_WAIT, 0, NULL, NULL, 0);
+}
@@ -0,0 +1,11 @@
+#include <signal.h>
+#include <stdio.h>
+
+int main() {
+    // Writes past RLIMIT_FSIZE then fail with EFBIG instead of killing the program
+    signal(SIGXFSZ, SIG_IGN);
+    for (int i = 0; i < 1000; i++) {
+        printf("Hello! output limit\n");
+    }
+    return 0;
+}
@@ -0,0 +1,13 @@
+#include <stdlib.h>
+
+const int block_size = 1 << 20;
+
+int main() {
+    // Nothing is printed when malloc() fails, the NULL pointer is written to instead
+    while (true) {
+        volatile char *block = (char *)malloc(block_size);
+        for (int i = 0; i < block_size; i += 4096) {
+            block[i] = 1;
+        }
+    }
+}
@@ -1,11 +1,12 @@
 #include <iostream>
-#include <random>
+#include <vector>
 
 using namespace std;
 
-const int maxn=5e7+10;
-int a[maxn];
+const int block_size=1<<18;
 
 int main() {
-    for(int i=0;i<maxn;i++) a[i]=random();
+    // Touch every allocated block so that it counts as resident memory
+    vector<vector<int>> blocks;
+    while(true) blocks.emplace_back(block_size, 1);
 }
@@ -0,0 +1,35 @@
+#include <pthread.h>
+
+#include <iostream>
+
+using namespace std;
+
+const int THREAD_COUNT = 4;
+long long sums[THREAD_COUNT];
+
+void *work(void *arg) {
+    long idx = (long)arg;
+    for (long long i = 0; i < 20000000; i++) {
+        sums[idx] += i % (idx + 2);
+    }
+    return NULL;
+}
+
+int main() {
+    string s;
+    cin >> s;
+    pthread_t threads[THREAD_COUNT];
+    // The default stack size follows RLIMIT_STACK, which may not fit in RLIMIT_AS
+    pthread_attr_t attr;
+    pthread_attr_init(&attr);
+    pthread_attr_setstacksize(&attr, 1 << 20);
+    for (long i = 0; i < THREAD_COUNT; i++) {
+        if (pthread_create(&threads[i], &attr, work, (void *)i) != 0) {
+            return 1;
+        }
+    }
+    for (int i = 0; i < THREAD_COUNT; i++) {
+        pthread_join(threads[i], NULL);
+    }
+    cout << "Hello! " << s << endl;
+}
@@ -0,0 +1,7 @@
+#include <stdio.h>
+
+int main() {
+    while (true) {
+        printf("Hello! output limit\n");
+    }
+}
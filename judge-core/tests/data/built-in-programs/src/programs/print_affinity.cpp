@@ -0,0 +1,12 @@
+#include <sched.h>
+#include <stdio.h>
+
+int main() {
+    // How many cores the program can run on
+    cpu_set_t cpu_set;
+    if (sched_getaffinity(0, sizeof(cpu_set), &cpu_set) != 0) {
+        return 1;
+    }
+    printf("%d\n", CPU_COUNT(&cpu_set));
+    return 0;
+}
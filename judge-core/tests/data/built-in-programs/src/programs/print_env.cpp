@@ -0,0 +1,13 @@
+#include <stdio.h>
+#include <stdlib.h>
+#include <unistd.h>
+
+int main(int argc, char *argv[]) {
+    char cwd[256];
+    if (argc < 2 || getcwd(cwd, sizeof(cwd)) == NULL) {
+        return 1;
+    }
+    printf("%s %s\n", getenv("GREETING"), argv[1]);
+    printf("%s\n%s\n%s\n", cwd, getenv("HOME"), getenv("LANG"));
+    return 0;
+}
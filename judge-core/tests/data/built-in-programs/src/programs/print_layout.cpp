@@ -0,0 +1,16 @@
+#include <stdio.h>
+#include <stdlib.h>
+#include <unistd.h>
+
+int main() {
+    // Different in every run unless address space layout randomization is disabled
+    int on_stack = 0;
+    void *on_heap = malloc(16);
+    printf("%p %p %p\n", (void *)&on_stack, on_heap, (void *)&main);
+
+    char hostname[256] = {0};
+    gethostname(hostname, sizeof(hostname) - 1);
+    printf("%s\n", hostname);
+    free(on_heap);
+    return 0;
+}
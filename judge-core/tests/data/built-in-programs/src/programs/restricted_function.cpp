@@ -0,0 +1,9 @@
+#include <stdio.h>
+#include <unistd.h>
+
+int main() {
+    // Creating processes is not allowed in the sandbox
+    pid_t pid = fork();
+    printf("%d\n", pid);
+    return 0;
+}
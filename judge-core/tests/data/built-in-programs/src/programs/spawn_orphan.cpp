@@ -0,0 +1,14 @@
+#include <stdio.h>
+#include <unistd.h>
+
+int main() {
+    // The child is left running after the parent exits
+    pid_t pid = fork();
+    if (pid == 0) {
+        while (true) {
+            sleep(1);
+        }
+    }
+    printf("%d\n", pid);
+    return 0;
+}
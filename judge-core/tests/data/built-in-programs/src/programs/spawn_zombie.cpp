@@ -0,0 +1,16 @@
+#include <stdio.h>
+#include <unistd.h>
+
+int main() {
+    // The child exits at once but is never waited by the parent
+    pid_t pid = fork();
+    if (pid == 0) {
+        _exit(0);
+    }
+    printf("%d\n", pid);
+    fflush(stdout);
+    while (true) {
+        sleep(1);
+    }
+    return 0;
+}
@@ -0,0 +1,14 @@
+#include <iostream>
+
+using namespace std;
+
+int main() {
+    string s;
+    cin >> s;
+    // Debug messages should not be mixed into the output,
+    // nor count toward the output limit
+    for (int i = 0; i < 1000; i++) {
+        cerr << "debug " << i << endl;
+    }
+    cout << "Hello! " << s << endl;
+}
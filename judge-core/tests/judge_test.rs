@@ -2,15 +2,20 @@ use std::path::PathBuf;
 
 use judge_core::{
     compiler::Language,
+    error::JudgeCoreError,
     judge::{
         builder::{JudgeBuilder, JudgeBuilderInput},
         interact::run_interact,
         result::JudgeVerdict,
-        CheckerConfig, JudgeConfig, ProgramConfig, RuntimeConfig, TestdataConfig,
+        CheckerConfig, JudgeConfig, ProgramConfig, RuntimeConfig, TestdataConfig, TimeAggregate,
+        TleRerunConfig,
     },
     package::PackageType,
-    run::executor::Executor,
-    sandbox::RlimitConfigs,
+    run::{backend::SandboxBackendKind, executor::Executor},
+    sandbox::{
+        cancellation::CancellationToken, jail::JailConfigs, namespace::NamespaceConfigs,
+        seccomp::is_notify_supported, CrashReason, RlimitConfigs,
+    },
 };
 
 use judge_core::judge::common::run_judge;
@@ -24,8 +29,15 @@ const TEST_CONFIG: RlimitConfigs = RlimitConfigs {
     cpu_limit: Some((1, 2)),
     nproc_limit: Some((1, 1)),
     fsize_limit: Some((1024, 1024)),
+    nofile_limit: Some((64, 64)),
+    core_limit: Some((0, 0)),
+    real_time_limit: Some(3),
+    max_threads: None,
+    cpu_pinning: None,
 };
 
+const TEST_STDERR_LIMIT_BYTES: u64 = 64;
+
 fn init() {
     let _ = env_logger::builder()
         .filter_level(log::LevelFilter::Debug)
@@ -36,6 +48,14 @@ fn build_test_config(program_executor: Executor) -> JudgeConfig {
     JudgeConfig {
         runtime: RuntimeConfig {
             rlimit_configs: TEST_CONFIG,
+            namespace_configs: NamespaceConfigs::all(),
+            jail_configs: Some(JailConfigs::default()),
+            seccomp_profile: None,
+            tle_rerun: None,
+            usage_sample_interval: None,
+            deterministic: false,
+            sandbox_backend: SandboxBackendKind::Native,
+            cancellation_token: None,
         },
         test_data: TestdataConfig {
             input_file_path: PathBuf::from(TEST_DATA_PATH)
@@ -50,6 +70,8 @@ fn build_test_config(program_executor: Executor) -> JudgeConfig {
         program: ProgramConfig {
             executor: program_executor,
             output_file_path: PathBuf::from(TEST_TEMP_PATH).join("program.out"),
+            stderr_file_path: PathBuf::from(TEST_TEMP_PATH).join("program.err"),
+            stderr_limit_bytes: TEST_STDERR_LIMIT_BYTES,
         },
     }
 }
@@ -74,6 +96,29 @@ fn test_run_judge() {
     }
 }
 
+#[cfg(feature = "async")]
+#[test]
+fn test_run_judge_async() {
+    init();
+    let program_path =
+        PathBuf::from(TEST_DATA_PATH).join("built-in-programs/build/src/programs/read_and_write");
+    let program_executor = Executor::new(Language::Cpp, program_path).unwrap();
+
+    let runner_config = build_test_config(program_executor);
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let result = runtime.block_on(judge_core::judge::common::run_judge_async(&runner_config));
+    if let Ok(result) = result {
+        log::debug!("{:?}", result);
+        assert_eq!(result.verdict, JudgeVerdict::Accepted);
+    } else {
+        log::debug!("{:?}", result);
+        unreachable!()
+    }
+}
+
 #[test]
 fn test_run_judge_python() {
     init();
@@ -94,6 +139,57 @@ fn test_run_judge_python() {
     }
 }
 
+#[test]
+fn test_run_stderr() {
+    init();
+    let program_path =
+        PathBuf::from(TEST_DATA_PATH).join("built-in-programs/build/src/programs/write_stderr");
+    let program_executor = Executor::new(Language::Cpp, program_path).unwrap();
+
+    let runner_config = build_test_config(program_executor);
+    let result = run_judge(&runner_config);
+    assert!(result.is_ok());
+    if let Ok(result) = result {
+        log::debug!("{:?}", result);
+        assert_eq!(result.verdict, JudgeVerdict::Accepted);
+        let stderr = result.stderr.unwrap();
+        assert!(stderr.starts_with("debug 0\n"));
+        assert_eq!(stderr.len() as u64, TEST_STDERR_LIMIT_BYTES);
+    }
+}
+
+#[test]
+fn test_run_multi_thread() {
+    init();
+    let program_path =
+        PathBuf::from(TEST_DATA_PATH).join("built-in-programs/build/src/programs/multi_thread");
+    let program_executor = Executor::new(Language::Cpp, program_path).unwrap();
+
+    let mut runner_config = build_test_config(program_executor.clone());
+    runner_config.runtime.rlimit_configs.max_threads = Some(5);
+    let result = run_judge(&runner_config);
+    assert!(result.is_ok());
+    if let Ok(result) = result {
+        log::debug!("{:?}", result);
+        assert_eq!(result.verdict, JudgeVerdict::Accepted);
+        assert!(result.real_time_usage > std::time::Duration::ZERO);
+        // The threads ran in parallel when there are cores for them
+        let cores = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+        if cores >= 2 {
+            assert!(result.real_time_usage * 3 < result.time_usage * 2);
+        }
+    }
+
+    // Single-threaded by default
+    let runner_config = build_test_config(program_executor);
+    let result = run_judge(&runner_config);
+    assert!(result.is_ok());
+    if let Ok(result) = result {
+        log::debug!("{:?}", result);
+        assert_eq!(result.verdict, JudgeVerdict::RestrictedFunction);
+    }
+}
+
 #[test]
 fn test_run_tle() {
     init();
@@ -110,6 +206,89 @@ fn test_run_tle() {
     }
 }
 
+#[test]
+fn test_run_cancelled() {
+    init();
+    let program_path =
+        PathBuf::from(TEST_DATA_PATH).join("built-in-programs/build/src/programs/infinite_loop");
+    let program_executor = Executor::new(Language::Cpp, program_path).unwrap();
+
+    let mut runner_config = build_test_config(program_executor);
+    let token = CancellationToken::new().unwrap();
+    runner_config.runtime.cancellation_token = Some(token.clone());
+    let canceller = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        token.cancel();
+    });
+    let begin = std::time::Instant::now();
+    let result = run_judge(&runner_config);
+    canceller.join().unwrap();
+    log::debug!("{:?}", result);
+    assert!(matches!(result, Err(JudgeCoreError::Cancelled)));
+    // Long before the time limit
+    assert!(begin.elapsed() < std::time::Duration::from_secs(1));
+}
+
+#[test]
+fn test_run_tle_rerun() {
+    init();
+    let program_path =
+        PathBuf::from(TEST_DATA_PATH).join("built-in-programs/build/src/programs/infinite_loop");
+    let program_executor = Executor::new(Language::Cpp, program_path).unwrap();
+
+    let mut runner_config = build_test_config(program_executor);
+    runner_config.runtime.tle_rerun = Some(TleRerunConfig::default());
+    let result = run_judge(&runner_config);
+    assert!(result.is_ok());
+    if let Ok(result) = result {
+        log::debug!("{:?}", result);
+        assert_eq!(result.verdict, JudgeVerdict::TimeLimitExceeded);
+        // Killed far past the limit, which is not borderline
+        assert!(result.attempts.is_empty());
+    }
+
+    // Killed by `RLIMIT_CPU` a second past the limit, which is borderline with this margin
+    runner_config.runtime.tle_rerun = Some(TleRerunConfig {
+        margin_percent: 150,
+        max_attempts: 2,
+        time_aggregate: TimeAggregate::Min,
+    });
+    let result = run_judge(&runner_config);
+    assert!(result.is_ok());
+    if let Ok(result) = result {
+        log::debug!("{:?}", result);
+        assert_eq!(result.verdict, JudgeVerdict::TimeLimitExceeded);
+        assert_eq!(result.attempts.len(), 2);
+        assert!(result
+            .attempts
+            .iter()
+            .all(|attempt| attempt.verdict == Some(JudgeVerdict::TimeLimitExceeded)));
+        let min_time = result
+            .attempts
+            .iter()
+            .map(|attempt| attempt.time_usage)
+            .min()
+            .unwrap();
+        assert_eq!(result.time_usage, min_time);
+    }
+}
+
+#[test]
+fn test_run_ile() {
+    init();
+    let program_path =
+        PathBuf::from(TEST_DATA_PATH).join("built-in-programs/build/src/programs/idleness_limit");
+    let program_executor = Executor::new(Language::Cpp, program_path).unwrap();
+
+    let runner_config = build_test_config(program_executor);
+    let result = run_judge(&runner_config);
+    assert!(result.is_ok());
+    if let Ok(result) = result {
+        log::debug!("{:?}", result);
+        assert_eq!(result.verdict, JudgeVerdict::IdlenessLimitExceeded);
+    }
+}
+
 #[test]
 fn test_run_mle() {
     init();
@@ -122,7 +301,123 @@ fn test_run_mle() {
     assert!(result.is_ok());
     if let Ok(result) = result {
         log::debug!("{:?}", result);
-        assert_eq!(result.verdict, JudgeVerdict::RuntimeError);
+        assert_eq!(result.verdict, JudgeVerdict::MemoryLimitExceeded);
+    }
+}
+
+#[test]
+fn test_run_mle_without_stderr() {
+    init();
+    // Crashes without telling why once the memory runs out
+    let program_path = PathBuf::from(TEST_DATA_PATH)
+        .join("built-in-programs/build/src/programs/malloc_until_null");
+    let program_executor = Executor::new(Language::Cpp, program_path).unwrap();
+
+    let runner_config = build_test_config(program_executor);
+    let result = run_judge(&runner_config);
+    assert!(result.is_ok());
+    if let Ok(result) = result {
+        log::debug!("{:?}", result);
+        assert_eq!(result.verdict, JudgeVerdict::MemoryLimitExceeded);
+    }
+}
+
+#[test]
+fn test_run_ole() {
+    init();
+    let program_path =
+        PathBuf::from(TEST_DATA_PATH).join("built-in-programs/build/src/programs/output_limit");
+    let program_executor = Executor::new(Language::Cpp, program_path).unwrap();
+
+    let runner_config = build_test_config(program_executor);
+    let result = run_judge(&runner_config);
+    assert!(result.is_ok());
+    if let Ok(result) = result {
+        log::debug!("{:?}", result);
+        assert_eq!(result.verdict, JudgeVerdict::OutputLimitExceeded);
+    }
+
+    // Not killed, but the writes past the limit fail
+    let program_path =
+        PathBuf::from(TEST_DATA_PATH).join("built-in-programs/build/src/programs/ignore_sigxfsz");
+    let program_executor = Executor::new(Language::Cpp, program_path).unwrap();
+
+    let runner_config = build_test_config(program_executor);
+    let result = run_judge(&runner_config);
+    assert!(result.is_ok());
+    if let Ok(result) = result {
+        log::debug!("{:?}", result);
+        assert_eq!(result.verdict, JudgeVerdict::OutputLimitExceeded);
+    }
+}
+
+#[test]
+fn test_run_restricted_function() {
+    init();
+    let program_path = PathBuf::from(TEST_DATA_PATH)
+        .join("built-in-programs/build/src/programs/restricted_function");
+    let program_executor = Executor::new(Language::Cpp, program_path).unwrap();
+
+    let runner_config = build_test_config(program_executor);
+    let result = run_judge(&runner_config);
+    assert!(result.is_ok());
+    if let Ok(result) = result {
+        log::debug!("{:?}", result);
+        assert_eq!(result.verdict, JudgeVerdict::RestrictedFunction);
+        // Without seccomp user notification the process is killed by `SIGSYS` instead,
+        // which does not tell the syscall
+        if is_notify_supported() {
+            let syscall = result.restricted_syscall.unwrap();
+            assert!(syscall == "clone" || syscall == "clone3", "{}", syscall);
+        } else {
+            assert_eq!(result.restricted_syscall, None);
+        }
+    }
+}
+
+#[test]
+fn test_run_crash() {
+    init();
+    for (program_name, crash_reason) in [
+        ("divide_by_zero", CrashReason::ArithmeticError),
+        ("bad_alloc", CrashReason::BadAlloc),
+    ] {
+        let program_path = PathBuf::from(TEST_DATA_PATH)
+            .join("built-in-programs/build/src/programs/".to_owned() + program_name);
+        let program_executor = Executor::new(Language::Cpp, program_path).unwrap();
+
+        let runner_config = build_test_config(program_executor);
+        let result = run_judge(&runner_config);
+        assert!(result.is_ok());
+        if let Ok(result) = result {
+            log::debug!("{:?}", result);
+            assert_eq!(result.verdict, JudgeVerdict::RuntimeError);
+            assert_eq!(result.crash_reason, Some(crash_reason));
+        }
+    }
+}
+
+#[test]
+fn test_run_jail() {
+    init();
+    let program_path =
+        PathBuf::from(TEST_DATA_PATH).join("built-in-programs/build/src/programs/read_answer");
+    let mut program_executor = Executor::new(Language::Cpp, program_path).unwrap();
+
+    let runner_config = build_test_config(program_executor.clone());
+    let answer_path = runner_config
+        .test_data
+        .answer_file_path
+        .canonicalize()
+        .unwrap();
+    program_executor.set_additional_args(vec![answer_path.to_string_lossy().to_string()]);
+    let runner_config = build_test_config(program_executor);
+    let result = run_judge(&runner_config);
+    assert!(result.is_ok());
+    if let Ok(result) = result {
+        log::debug!("{:?}", result);
+        // The answer file is not visible inside the jail
+        assert_eq!(result.verdict, JudgeVerdict::WrongAnswer);
     }
 }
 
@@ -158,13 +453,10 @@ fn test_run_interact() {
         &PathBuf::from(TEST_TEMP_PATH).join("interact.out"),
     );
     match result {
-        Ok(Some(result)) => {
+        Ok(result) => {
             log::debug!("{:?}", result);
             assert!(result.verdict == JudgeVerdict::Accepted);
         }
-        Ok(None) => {
-            log::debug!("Ignoring this result, for it's from a fork child process");
-        }
         Err(e) => {
             log::error!("meet error: {:?}", e);
             unreachable!()
@@ -182,6 +474,8 @@ fn test_build_icpc() {
         src_language: Language::Cpp,
         src_path: PathBuf::from(TEST_DATA_PATH)
             .join("built-in-programs/src/programs/read_and_write.cpp"),
+        seccomp_profile: None,
+        cancellation_token: None,
     })
     .unwrap();
     log::info!("builder: {:?}", builder);
@@ -0,0 +1,385 @@
+use std::{
+    fs::{self, File},
+    os::unix::io::AsRawFd,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+
+use judge_core::{
+    cgroup::get_cgroup_runs_root,
+    compiler::Language,
+    run::{
+        backend::{SandboxBackendKind, SandboxSpec},
+        executor::{Executor, ExecutorConfigs},
+        sandbox::ExecutorSandbox,
+    },
+    sandbox::{
+        cpu::SchedPolicy,
+        namespace::{is_rootless_supported, NamespaceConfigs},
+        seccomp::{format_learned_profile, SeccompProfile, SeccompProfiles},
+        ExitReason, RlimitConfigs, DETERMINISTIC_HOSTNAME,
+    },
+};
+
+const TEST_DATA_PATH: &str = "tests/data";
+const TEST_TEMP_PATH: &str = "tests/temp";
+
+// Creating processes is allowed, so no seccomp profile is used either
+const TEST_CONFIG: RlimitConfigs = RlimitConfigs {
+    stack_limit: Some((64 * 1024 * 1024, 64 * 1024 * 1024)),
+    as_limit: Some((64 * 1024 * 1024, 64 * 1024 * 1024)),
+    cpu_limit: Some((1, 2)),
+    nproc_limit: None,
+    fsize_limit: Some((1024, 1024)),
+    nofile_limit: Some((64, 64)),
+    core_limit: Some((0, 0)),
+    real_time_limit: Some(1),
+    max_threads: None,
+    cpu_pinning: None,
+};
+
+fn init() {
+    let _ = env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .try_init();
+}
+
+fn get_program_executor(program_name: &str) -> Executor {
+    let program_path = PathBuf::from(TEST_DATA_PATH)
+        .join("built-in-programs/build/src/programs/".to_owned() + program_name);
+    Executor::new(Language::Cpp, program_path).unwrap()
+}
+
+/// Run the executor and return its exit reason, with what it printed.
+fn run_executor(
+    executor: Executor,
+    rlimit_configs: RlimitConfigs,
+    output_name: &str,
+) -> (ExitReason, String) {
+    fs::create_dir_all(TEST_TEMP_PATH).unwrap();
+    let output_path = PathBuf::from(TEST_TEMP_PATH).join(output_name);
+    let output_file = File::create(&output_path).unwrap();
+
+    let mut sandbox = ExecutorSandbox::new(
+        executor,
+        rlimit_configs,
+        None,
+        Some(output_file.as_raw_fd()),
+        None,
+    )
+    .unwrap();
+    sandbox.spawn().unwrap();
+    let exit_info = sandbox.wait().unwrap();
+    log::debug!("{:?}", exit_info);
+
+    (
+        exit_info.exit_reason,
+        fs::read_to_string(&output_path).unwrap(),
+    )
+}
+
+/// Run the program and return its exit reason, with the pid it printed.
+fn run_program(program_name: &str) -> (ExitReason, i32) {
+    let (exit_reason, output) = run_executor(
+        get_program_executor(program_name),
+        TEST_CONFIG,
+        &format!("{}.out", program_name),
+    );
+    (exit_reason, output.trim().parse::<i32>().unwrap())
+}
+
+/// Killed processes are reaped by their new parent, which takes a moment.
+fn wait_process_gone(pid: i32) -> bool {
+    let proc_path = PathBuf::from(format!("/proc/{}", pid));
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while Instant::now() < deadline {
+        if !proc_path.exists() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+#[test]
+fn test_kill_orphan() {
+    init();
+    let (exit_reason, orphan_pid) = run_program("spawn_orphan");
+    assert_eq!(exit_reason, ExitReason::Exited(0));
+    assert!(wait_process_gone(orphan_pid));
+}
+
+#[test]
+fn test_reap_zombie() {
+    init();
+    let (exit_reason, zombie_pid) = run_program("spawn_zombie");
+    assert_eq!(exit_reason, ExitReason::KilledByTimeLimit);
+    assert!(wait_process_gone(zombie_pid));
+}
+
+#[test]
+fn test_executor_configs() {
+    init();
+    let mut executor = get_program_executor("print_env");
+    fs::create_dir_all(TEST_TEMP_PATH).unwrap();
+    let scratch_dir = fs::canonicalize(TEST_TEMP_PATH).unwrap();
+    executor.set_scratch_dir(scratch_dir.clone());
+    let mut configs = ExecutorConfigs::default();
+    configs
+        .envs
+        .insert("GREETING".to_string(), "Hello!".to_string());
+    configs.cwd = Some(PathBuf::from("/"));
+    configs.args = vec!["world!".to_string()];
+    executor.apply_configs(&configs);
+
+    let (exit_reason, output) = run_executor(executor, TEST_CONFIG, "print_env.out");
+    assert_eq!(exit_reason, ExitReason::Exited(0));
+    // The working directory of the configs takes precedence over the scratch dir
+    assert_eq!(
+        output,
+        format!("Hello! world!\n/\n{}\nC.UTF-8\n", scratch_dir.display())
+    );
+}
+
+#[test]
+fn test_cpu_pinning() {
+    init();
+    let rlimit_configs = RlimitConfigs {
+        cpu_pinning: Some(SchedPolicy::Other),
+        ..TEST_CONFIG
+    };
+    let (exit_reason, output) = run_executor(
+        get_program_executor("print_affinity"),
+        rlimit_configs,
+        "print_affinity.out",
+    );
+    assert_eq!(exit_reason, ExitReason::Exited(0));
+    assert_eq!(output, "1\n");
+}
+
+#[test]
+fn test_usage_samples() {
+    init();
+    let mut sandbox = ExecutorSandbox::new(
+        get_program_executor("grow_memory"),
+        TEST_CONFIG,
+        None,
+        None,
+        None,
+    )
+    .unwrap();
+    sandbox.set_usage_sample_interval(Some(Duration::from_millis(20)));
+    sandbox.spawn().unwrap();
+    let exit_info = sandbox.wait().unwrap();
+    log::debug!("{:?}", exit_info.usage_samples);
+    assert_eq!(exit_info.exit_reason, ExitReason::Exited(0));
+
+    let samples = exit_info.usage_samples;
+    assert!(samples.len() >= 2);
+    assert!(samples
+        .windows(2)
+        .all(|pair| pair[0].elapsed < pair[1].elapsed));
+    assert!(samples.last().unwrap().memory_bytes > samples.first().unwrap().memory_bytes);
+}
+
+#[test]
+fn test_usage_samples_with_pid_namespace() {
+    init();
+    if !nix::unistd::getuid().is_root() && !is_rootless_supported() {
+        println!("Skipped: namespaces require root or rootless mode");
+        return;
+    }
+    let mut sandbox = ExecutorSandbox::new(
+        get_program_executor("grow_memory"),
+        TEST_CONFIG,
+        None,
+        None,
+        None,
+    )
+    .unwrap();
+    sandbox.set_namespace_configs(NamespaceConfigs {
+        pid: true,
+        ..Default::default()
+    });
+    sandbox.set_usage_sample_interval(Some(Duration::from_millis(20)));
+    sandbox.spawn().unwrap();
+    let exit_info = sandbox.wait().unwrap();
+    log::debug!("{:?}", exit_info.usage_samples);
+    assert_eq!(exit_info.exit_reason, ExitReason::Exited(0));
+
+    // Sampled from the program rather than the process waiting for it outside the namespace
+    let samples = exit_info.usage_samples;
+    assert!(samples.len() >= 2);
+    assert!(samples.last().unwrap().memory_bytes > samples.first().unwrap().memory_bytes);
+}
+
+#[test]
+fn test_syscall_learning() {
+    init();
+    fs::create_dir_all(TEST_TEMP_PATH).unwrap();
+    let output_file = File::create(PathBuf::from(TEST_TEMP_PATH).join("learning.out")).unwrap();
+    let mut sandbox = ExecutorSandbox::new(
+        get_program_executor("print_affinity"),
+        TEST_CONFIG,
+        None,
+        Some(output_file.as_raw_fd()),
+        None,
+    )
+    .unwrap();
+    sandbox.enable_syscall_learning().unwrap();
+    sandbox.spawn().unwrap();
+    let exit_info = sandbox.wait().unwrap();
+    log::debug!("{:?}", exit_info.learned_syscalls);
+    assert_eq!(exit_info.exit_reason, ExitReason::Exited(0));
+    for syscall in ["execve", "sched_getaffinity", "write", "exit_group"] {
+        assert!(exit_info
+            .learned_syscalls
+            .iter()
+            .any(|learned_syscall| learned_syscall.name == syscall));
+    }
+
+    // The learned profile is enough for running the program again
+    let yaml = format_learned_profile("learned", &exit_info.learned_syscalls);
+    let profile = SeccompProfiles::from_yaml(&yaml)
+        .unwrap()
+        .resolve("learned")
+        .unwrap();
+    assert_eq!(
+        profile,
+        SeccompProfile::from_learned_syscalls(&exit_info.learned_syscalls)
+    );
+    let mut sandbox = ExecutorSandbox::new(
+        get_program_executor("print_affinity"),
+        TEST_CONFIG,
+        None,
+        Some(output_file.as_raw_fd()),
+        Some(&profile),
+    )
+    .unwrap();
+    sandbox.spawn().unwrap();
+    assert_eq!(sandbox.wait().unwrap().exit_reason, ExitReason::Exited(0));
+}
+
+#[test]
+fn test_poll_exit() {
+    init();
+    // Never wakes up by itself, so only the timer of the real time limit makes the event fd readable
+    let mut sandbox = SandboxBackendKind::Native
+        .create(SandboxSpec::new(
+            get_program_executor("idleness_limit"),
+            TEST_CONFIG,
+        ))
+        .unwrap();
+    sandbox.spawn().unwrap();
+    let begin = Instant::now();
+    while !sandbox.poll_exit().unwrap() {
+        let mut poll_fds = [PollFd::new(
+            sandbox.get_event_fd().unwrap(),
+            PollFlags::POLLIN,
+        )];
+        poll(&mut poll_fds, PollTimeout::NONE).unwrap();
+    }
+    assert!(begin.elapsed() < Duration::from_secs(2));
+    let exit_info = sandbox.wait().unwrap();
+    assert_eq!(exit_info.exit_reason, ExitReason::KilledByTimeLimit);
+}
+
+#[test]
+fn test_deterministic() {
+    init();
+    let run = |output_name: &str| {
+        let output_path = PathBuf::from(TEST_TEMP_PATH).join(output_name);
+        let output_file = File::create(&output_path).unwrap();
+        let mut sandbox = ExecutorSandbox::new(
+            get_program_executor("print_layout"),
+            TEST_CONFIG,
+            None,
+            Some(output_file.as_raw_fd()),
+            None,
+        )
+        .unwrap();
+        sandbox.set_deterministic(true);
+        sandbox.spawn().unwrap();
+        assert_eq!(sandbox.wait().unwrap().exit_reason, ExitReason::Exited(0));
+        fs::read_to_string(&output_path).unwrap()
+    };
+    fs::create_dir_all(TEST_TEMP_PATH).unwrap();
+    let output = run("print_layout_0.out");
+    assert_eq!(output, run("print_layout_1.out"));
+    if nix::unistd::getuid().is_root() {
+        assert!(output.ends_with(&format!("\n{}\n", DETERMINISTIC_HOSTNAME)));
+    }
+}
+
+#[test]
+fn test_cgroup_with_pid_namespace() {
+    init();
+    if get_cgroup_runs_root().is_none() {
+        println!("Skipped: cgroup v2 is not available");
+        return;
+    }
+    if !nix::unistd::getuid().is_root() && !is_rootless_supported() {
+        println!("Skipped: namespaces require root or rootless mode");
+        return;
+    }
+    fs::create_dir_all(TEST_TEMP_PATH).unwrap();
+    let output_path = PathBuf::from(TEST_TEMP_PATH).join("cgroup_pid_namespace.out");
+    let output_file = File::create(&output_path).unwrap();
+    // `pids.max` is 1, which leaves no room for the processes forked for the pid namespace
+    let rlimit_configs = RlimitConfigs {
+        nproc_limit: Some((1, 1)),
+        ..TEST_CONFIG
+    };
+    let mut sandbox = ExecutorSandbox::new(
+        get_program_executor("print_layout"),
+        rlimit_configs,
+        None,
+        Some(output_file.as_raw_fd()),
+        None,
+    )
+    .unwrap();
+    sandbox.set_namespace_configs(NamespaceConfigs {
+        pid: true,
+        ..Default::default()
+    });
+    sandbox.spawn().unwrap();
+    let exit_info = sandbox.wait().unwrap();
+    log::debug!("{:?}", exit_info);
+    assert_eq!(exit_info.exit_reason, ExitReason::Exited(0));
+    assert!(exit_info.resource_usage.max_rss > 0);
+    assert!(!fs::read_to_string(&output_path).unwrap().is_empty());
+}
+
+#[test]
+fn test_rootless_spawn() {
+    init();
+    if nix::unistd::getuid().is_root() {
+        println!("Skipped: rootless mode is only used without root");
+        return;
+    }
+    if !is_rootless_supported() {
+        println!("Skipped: unprivileged user namespaces are not available");
+        return;
+    }
+    fs::create_dir_all(TEST_TEMP_PATH).unwrap();
+    let output_path = PathBuf::from(TEST_TEMP_PATH).join("rootless.out");
+    let output_file = File::create(&output_path).unwrap();
+    // No sandbox user pool is needed, the program keeps the ids of the test
+    let mut sandbox = ExecutorSandbox::new(
+        get_program_executor("print_layout"),
+        TEST_CONFIG,
+        None,
+        Some(output_file.as_raw_fd()),
+        None,
+    )
+    .unwrap();
+    sandbox.set_namespace_configs(NamespaceConfigs::all());
+    sandbox.spawn().unwrap();
+    let exit_info = sandbox.wait().unwrap();
+    log::debug!("{:?}", exit_info);
+    assert_eq!(exit_info.exit_reason, ExitReason::Exited(0));
+    assert!(!fs::read_to_string(&output_path).unwrap().is_empty());
+}
@@ -0,0 +1,66 @@
+use actix_web::{post, web, HttpResponse};
+use judge_core::sandbox::cancellation::CancellationToken;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::error::ServiceError;
+
+lazy_static! {
+    /// Tokens of the running judges by judge UID
+    static ref CANCELLATION_TOKENS: Mutex<HashMap<String, CancellationToken>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Create the token of a judge, which can then be cancelled by its UID.
+pub fn register(judge_uid: &str) -> anyhow::Result<CancellationToken> {
+    let token = CancellationToken::new()
+        .map_err(|e| anyhow::anyhow!("Failed to create cancellation token: {:?}", e))?;
+    let mut tokens = CANCELLATION_TOKENS
+        .lock()
+        .map_err(|e| anyhow::anyhow!("Failed to lock cancellation tokens: {:?}", e))?;
+    tokens.insert(judge_uid.to_string(), token.clone());
+    Ok(token)
+}
+
+pub fn unregister(judge_uid: &str) {
+    if let Ok(mut tokens) = CANCELLATION_TOKENS.lock() {
+        tokens.remove(judge_uid);
+    }
+}
+
+#[derive(utoipa::OpenApi)]
+#[openapi(paths(cancel_judge))]
+pub struct CancelApiDoc;
+
+pub fn route(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/cancel").service(cancel_judge));
+}
+
+#[utoipa::path(
+    context_path = "/api/v1/cancel",
+    responses(
+        (status = 200, description = "Judge cancelled"),
+        (status = 400, description = "No running judge with the UID")
+    )
+)]
+#[post("/{judge_uid}")]
+pub async fn cancel_judge(judge_uid: web::Path<String>) -> Result<HttpResponse, ServiceError> {
+    let tokens = CANCELLATION_TOKENS.lock().map_err(|e| {
+        ServiceError::InternalError(anyhow::anyhow!(
+            "Failed to lock cancellation tokens: {:?}",
+            e
+        ))
+    })?;
+    match tokens.get(judge_uid.as_str()) {
+        Some(token) => {
+            log::info!("Cancelling judge {}", judge_uid);
+            token.cancel();
+            Ok(HttpResponse::Ok().finish())
+        }
+        None => Err(ServiceError::BadRequestWithMsg(
+            anyhow::anyhow!("Judge not found"),
+            format!("No running judge with UID {}", judge_uid),
+        )),
+    }
+}
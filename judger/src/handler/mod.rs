@@ -1,3 +1,4 @@
+pub mod cancel;
 mod greet;
 pub mod state;
 
@@ -15,7 +16,8 @@ pub fn route(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/api/v1")
             .service(greet::greet)
-            .configure(state::route),
+            .configure(state::route)
+            .configure(cancel::route),
     )
     .service(
         utoipa_swagger_ui::SwaggerUi::new("/swagger-ui/{_:.*}").urls(vec![
@@ -27,6 +29,10 @@ pub fn route(cfg: &mut web::ServiceConfig) {
                 utoipa_swagger_ui::Url::new("state", "/api-docs/state.json"),
                 state::StateApiDoc::openapi(),
             ),
+            (
+                utoipa_swagger_ui::Url::new("cancel", "/api-docs/cancel.json"),
+                cancel::CancelApiDoc::openapi(),
+            ),
         ]),
     );
 }
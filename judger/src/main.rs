@@ -8,16 +8,31 @@ mod worker;
 extern crate serde_derive;
 extern crate lazy_static;
 
-use std::{fs, path::PathBuf, time::Duration};
+use std::{
+    fs::{self, File},
+    os::unix::io::AsRawFd,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
 use actix_web::{App, HttpServer};
 use agent::{platform, rclone::RcloneClient};
+use judge_core::compiler::{Compiler, Language};
+use judge_core::error::JudgeCoreError;
 use judge_core::judge::{
     result::{JudgeResultInfo, JudgeVerdict},
     JudgeConfig,
 };
+use judge_core::run::{executor::Executor, sandbox::ExecutorSandbox};
+use judge_core::sandbox::cpu::{init_cpu_pool, parse_core_list};
+use judge_core::sandbox::jail::{JailConfigs, JAIL_SCRATCH_PATH};
+use judge_core::sandbox::namespace::NamespaceConfigs;
+use judge_core::sandbox::seccomp::{format_learned_profile, init_seccomp_profiles};
+use judge_core::sandbox::user::{init_sandbox_user_pool, is_root, SandboxUserRange};
+use judge_core::sandbox::{ExitReason, SCRIPT_LIMIT_CONFIG};
 use option::JudgerCommad;
-use worker::JudgeWorker;
+use worker::{JudgeWorker, RunOptions};
 
 #[actix_web::main]
 // The button provided by rust-analyzer will not work as expected here
@@ -25,6 +40,32 @@ use worker::JudgeWorker;
 async fn main() -> std::io::Result<()> {
     let opt = option::load_option();
 
+    if let Some(seccomp_profiles_path) = opt.seccomp_profiles_path.as_ref() {
+        if let Err(e) = init_seccomp_profiles(seccomp_profiles_path) {
+            log::error!("Failed to load seccomp profiles: {:?}", e);
+            return Ok(());
+        }
+    }
+
+    // Without root the programs keep the ids of the judger, see `namespace::is_rootless_supported()`
+    if is_root() {
+        if let Err(e) = SandboxUserRange::from_username(&opt.sandbox_username, opt.sandbox_id_count)
+            .and_then(init_sandbox_user_pool)
+        {
+            log::error!("Failed to prepare sandbox users: {:?}", e);
+            return Ok(());
+        }
+    } else {
+        log::info!("Not running as root, sandbox users are not used");
+    }
+
+    if let Some(pinned_cores) = opt.pinned_cores.as_ref() {
+        if let Err(e) = parse_core_list(pinned_cores).and_then(init_cpu_pool) {
+            log::error!("Failed to prepare pinned cores: {:?}", e);
+            return Ok(());
+        }
+    }
+
     let maybe_rclone_client = if opt.enable_rclone {
         Some(agent::rclone::RcloneClient::new(
             opt.rclone_config_path.clone(),
@@ -33,6 +74,10 @@ async fn main() -> std::io::Result<()> {
         None
     };
 
+    let run_options = RunOptions {
+        cpu_pinning: opt.cpu_pinning,
+        sandbox_backend: opt.sandbox_backend,
+    };
     match opt.cmd {
         option::JudgerCommad::Serve {
             platform_uri,
@@ -44,10 +89,10 @@ async fn main() -> std::io::Result<()> {
                 maybe_rclone_client,
                 opt.problem_package_bucket,
                 opt.problem_package_dir,
-                platform_uri.clone(),
-                internal_token,
+                platform::PlatformClient::new(platform_uri.clone(), internal_token),
                 fetch_task_interval,
                 port,
+                run_options,
             )
             .await
         }
@@ -63,9 +108,23 @@ async fn main() -> std::io::Result<()> {
                 problem_slug,
                 language,
                 src_path,
+                run_options,
             )
             .await
         }
+        JudgerCommad::LearnSeccomp {
+            language,
+            src_path,
+            input_path,
+            profile_name,
+            output_path,
+        } => {
+            if let Err(e) = learn_seccomp(language, src_path, input_path, profile_name, output_path)
+            {
+                log::error!("Failed to learn seccomp profile: {:?}", e);
+            }
+            Ok(())
+        }
     }
 }
 
@@ -73,19 +132,18 @@ async fn serve(
     maybe_rclone_client: Option<RcloneClient>,
     problem_package_bucket: String,
     problem_package_dir: PathBuf,
-    platform_uri: String,
-    internal_token: String,
+    platform_client: platform::PlatformClient,
     fetch_task_interval: u64,
     port: u16,
+    run_options: RunOptions,
 ) -> std::io::Result<()> {
-    let platform_client = platform::PlatformClient::new(platform_uri.clone(), internal_token);
-
     let worker = match JudgeWorker::new(
         Some(platform_client),
         maybe_rclone_client,
         fetch_task_interval,
         problem_package_bucket.clone(),
         problem_package_dir.clone(),
+        run_options,
     ) {
         Ok(worker) => worker,
         Err(e) => {
@@ -93,7 +151,7 @@ async fn serve(
             return Ok(());
         }
     };
-    tokio::spawn(async move { worker.run().await });
+    tokio::spawn(async move { Arc::new(worker).run().await });
 
     HttpServer::new(move || {
         App::new()
@@ -112,6 +170,7 @@ async fn judge(
     problem_slug: String,
     language: judge_core::compiler::Language,
     src_path: std::path::PathBuf,
+    run_options: RunOptions,
 ) -> std::io::Result<()> {
     // Read code from src_path
     let code = match fs::read_to_string(src_path) {
@@ -128,6 +187,7 @@ async fn judge(
         0,
         problem_package_bucket.clone(),
         problem_package_dir.clone(),
+        run_options,
     ) {
         Ok(worker) => worker,
         Err(e) => {
@@ -136,7 +196,10 @@ async fn judge(
         }
     };
 
-    let prepare_result = worker.prepare_judge(problem_slug.clone(), language, code.clone());
+    let worker = Arc::new(worker);
+    let prepare_result = worker
+        .prepare_judge_async(problem_slug.clone(), language, code.clone(), None)
+        .await;
     if prepare_result.is_err() {
         log::error!("Failed to prepare judge: {:?}", prepare_result.err());
         return Ok(());
@@ -153,13 +216,19 @@ async fn judge(
             runtime: judge.runtime_config.clone(),
         };
 
-        let judge_result = worker.run_judge(judge_config);
+        let judge_result = worker.run_judge(judge_config).await;
         let mut result = JudgeResultInfo {
             verdict: JudgeVerdict::SystemError,
             time_usage: Duration::from_secs(0),
+            real_time_usage: Duration::from_secs(0),
             memory_usage_bytes: 0,
             exit_status: -1,
             checker_exit_status: -1,
+            restricted_syscall: None,
+            crash_reason: None,
+            stderr: None,
+            attempts: vec![],
+            usage_samples: vec![],
         };
         match judge_result {
             Ok(r) => {
@@ -177,3 +246,54 @@ async fn judge(
     println!("{:?}", verdict);
     Ok(())
 }
+
+fn learn_seccomp(
+    language: Language,
+    src_path: PathBuf,
+    input_path: Option<PathBuf>,
+    profile_name: String,
+    output_path: PathBuf,
+) -> Result<(), JudgeCoreError> {
+    let runtime_path = std::env::temp_dir().join("judger-learn-seccomp");
+    fs::create_dir_all(&runtime_path)?;
+    let program_path = runtime_path.join("program");
+    Compiler::new(language, vec![]).compile(&src_path, &program_path)?;
+    let mut executor = Executor::new(language, program_path)?;
+    executor.set_scratch_dir(PathBuf::from(JAIL_SCRATCH_PATH));
+
+    // Run in the same way as a user program, so that the jail is taken into account
+    let input_file = match input_path {
+        Some(input_path) => Some(File::open(input_path)?),
+        None => None,
+    };
+    let output_file = File::create(runtime_path.join("program.out"))?;
+    let mut sandbox = ExecutorSandbox::new(
+        executor,
+        SCRIPT_LIMIT_CONFIG.clone(),
+        input_file.as_ref().map(|file| file.as_raw_fd()),
+        Some(output_file.as_raw_fd()),
+        None,
+    )?;
+    sandbox.set_namespace_configs(NamespaceConfigs::all());
+    sandbox.set_jail_configs(Some(JailConfigs::default()))?;
+    sandbox.enable_syscall_learning()?;
+    sandbox.spawn()?;
+    let exit_info = sandbox.wait()?;
+    if exit_info.exit_reason != ExitReason::Exited(0) {
+        log::warn!(
+            "Reference program exited abnormally: {:?}",
+            exit_info.exit_reason
+        );
+    }
+
+    fs::write(
+        &output_path,
+        format_learned_profile(&profile_name, &exit_info.learned_syscalls),
+    )?;
+    println!(
+        "{} syscalls written to {:?}",
+        exit_info.learned_syscalls.len(),
+        output_path
+    );
+    Ok(())
+}
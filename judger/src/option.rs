@@ -25,6 +25,27 @@ pub struct JudgerOpt {
     /// Where to store problem package
     #[structopt(env = "PROBLEM_PACKAGE_PATH", default_value = "problem-packages")]
     pub problem_package_dir: PathBuf,
+    /// YAML file of seccomp profiles, the built-in profiles are used when not set
+    #[structopt(long, env = "SECCOMP_PROFILES_PATH")]
+    pub seccomp_profiles_path: Option<PathBuf>,
+    /// Account whose uid and gid start the id range of sandboxed runs
+    #[structopt(long, env = "SANDBOX_USERNAME", default_value = "judger_sandbox")]
+    pub sandbox_username: String,
+    /// How many consecutive uids and gids can be leased to concurrent runs,
+    /// the ids after the sandbox account's should be reserved for the judger.
+    /// Runs wait for a free pair, and interactive judging runs two programs at once
+    #[structopt(long, env = "SANDBOX_ID_COUNT", default_value = "2")]
+    pub sandbox_id_count: u32,
+    /// Pin each run to its own core with the scheduling policy:
+    /// other, batch, fifo:<priority> or rr:<priority>
+    #[structopt(long, env = "CPU_PINNING")]
+    pub cpu_pinning: Option<judge_core::sandbox::cpu::SchedPolicy>,
+    /// Cores handed out to pinned runs like `2,3,5-7`, all but the first core by default
+    #[structopt(long, env = "PINNED_CORES")]
+    pub pinned_cores: Option<String>,
+    /// How the programs of judging are sandboxed, only `native` for now
+    #[structopt(long, env = "SANDBOX_BACKEND", default_value = "native")]
+    pub sandbox_backend: judge_core::run::backend::SandboxBackendKind,
 }
 
 #[derive(StructOpt, Debug, Clone)]
@@ -50,6 +71,22 @@ pub enum JudgerCommad {
         #[structopt(short, long)]
         src_path: PathBuf,
     },
+    /// Runs a reference program recording its syscalls, and writes them as a seccomp profile
+    LearnSeccomp {
+        #[structopt(short, long)]
+        language: judge_core::compiler::Language,
+        #[structopt(short, long)]
+        src_path: PathBuf,
+        /// Fed to the program as stdin
+        #[structopt(short, long)]
+        input_path: Option<PathBuf>,
+        /// Name of the profile in the written file
+        #[structopt(short, long)]
+        profile_name: String,
+        /// Where to write the profiles file, which can be merged into `SECCOMP_PROFILES_PATH`
+        #[structopt(short, long)]
+        output_path: PathBuf,
+    },
 }
 
 /// Try to load env from a .env file, if not found, fallback to ENV
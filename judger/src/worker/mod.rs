@@ -1,17 +1,21 @@
 use crate::agent::platform::PlatformClient;
 use crate::agent::rclone::RcloneClient;
-use crate::handler::state;
+use crate::handler::{cancel, state};
 use anyhow::Error;
 use judge_core::compiler::Language;
 use judge_core::error::JudgeCoreError;
 use judge_core::judge;
 use judge_core::judge::result::JudgeVerdict;
+use judge_core::run::backend::SandboxBackendKind;
+use judge_core::sandbox::cancellation::CancellationToken;
+use judge_core::sandbox::cpu::SchedPolicy;
 use judge_core::{
     judge::builder::{JudgeBuilder, JudgeBuilderInput},
     judge::result::JudgeResultInfo,
     judge::JudgeConfig,
     package::PackageType,
 };
+use std::sync::Arc;
 use std::time::Duration;
 use std::{fs, path::PathBuf};
 use tokio::time::interval;
@@ -22,6 +26,15 @@ pub struct JudgeWorker {
     maybe_rclone_client: Option<RcloneClient>,
     package_bucket: String,
     package_dir: PathBuf,
+    run_options: RunOptions,
+}
+
+/// Settings of the deployment applied to every judge
+#[derive(Debug, Clone, Copy)]
+pub struct RunOptions {
+    /// Each run leases a distinct core, see `PINNED_CORES`
+    pub cpu_pinning: Option<SchedPolicy>,
+    pub sandbox_backend: SandboxBackendKind,
 }
 
 impl JudgeWorker {
@@ -31,6 +44,7 @@ impl JudgeWorker {
         interval_sec: u64,
         package_bucket: String,
         package_dir: PathBuf,
+        run_options: RunOptions,
     ) -> Result<Self, Error> {
         if let Some(rclone_client) = maybe_rclone_client.as_ref() {
             if rclone_client.is_avaliable() {
@@ -46,10 +60,11 @@ impl JudgeWorker {
             interval_sec,
             package_bucket,
             package_dir,
+            run_options,
         })
     }
 
-    pub async fn run(&self) {
+    pub async fn run(self: Arc<Self>) {
         log::info!("judge task worker started");
 
         if self.maybe_platform_client.is_none() {
@@ -72,23 +87,49 @@ impl JudgeWorker {
                     // TODO: handle failure for set_busy here & return the task to the queue
                     let _ = state::set_busy();
 
-                    let prepare_result = self.prepare_judge(
-                        task.problem_slug.clone(),
-                        task.language,
-                        task.code.clone(),
-                    );
-                    if let Err(e) = prepare_result {
-                        log::debug!("Failed to prepare judge: {:?}", e);
-                        let mut verdict = JudgeVerdict::SystemError;
-                        if let JudgeCoreError::CompileError(_) = e {
-                            verdict = JudgeVerdict::CompileError;
+                    // Registered before compiling, so that compiling is cancellable as well
+                    let cancellation_token = match cancel::register(&task.judge_uid) {
+                        Ok(token) => Some(token),
+                        Err(e) => {
+                            log::warn!("Judge {} is not cancellable: {:?}", task.judge_uid, e);
+                            None
+                        }
+                    };
+                    let is_cancelled = || {
+                        cancellation_token
+                            .as_ref()
+                            .is_some_and(|token| token.is_cancelled())
+                    };
+
+                    let prepare_result = self
+                        .prepare_judge_async(
+                            task.problem_slug.clone(),
+                            task.language,
+                            task.code.clone(),
+                            cancellation_token.clone(),
+                        )
+                        .await;
+                    let prepare_verdict = match &prepare_result {
+                        _ if is_cancelled() => {
+                            log::info!("Judge {} is cancelled", task.judge_uid);
+                            Some(JudgeVerdict::Cancelled)
                         }
+                        Ok(_) => None,
+                        Err(JudgeCoreError::CompileError(_)) => Some(JudgeVerdict::CompileError),
+                        Err(e) => {
+                            log::debug!("Failed to prepare judge: {:?}", e);
+                            Some(JudgeVerdict::SystemError)
+                        }
+                    };
+                    if let Some(verdict) = prepare_verdict {
+                        cancel::unregister(&task.judge_uid);
                         let _ = platform_client
                             .report_judge_task(&task.redis_stream_id.clone(), verdict)
                             .await
                             .map_err(|e| {
                                 log::debug!("Failed to report judge task: {:?}", e);
                             });
+                        state::set_idle();
                         continue;
                     }
                     let judge: JudgeBuilder = prepare_result.unwrap();
@@ -114,13 +155,24 @@ impl JudgeWorker {
                             runtime: judge.runtime_config.clone(),
                         };
 
-                        let judge_result = self.run_judge(judge_config);
+                        let judge_result = self.run_judge(judge_config).await;
+                        if is_cancelled() {
+                            log::info!("Judge {} is cancelled", task.judge_uid);
+                            verdict = JudgeVerdict::Cancelled;
+                            break;
+                        }
                         let mut result = JudgeResultInfo {
                             verdict: JudgeVerdict::SystemError,
                             time_usage: Duration::from_secs(0),
+                            real_time_usage: Duration::from_secs(0),
                             memory_usage_bytes: 0,
                             exit_status: -1,
                             checker_exit_status: -1,
+                            restricted_syscall: None,
+                            crash_reason: None,
+                            stderr: None,
+                            attempts: vec![],
+                            usage_samples: vec![],
                         };
                         match judge_result {
                             Ok(r) => {
@@ -148,6 +200,7 @@ impl JudgeWorker {
                         }
                     }
 
+                    cancel::unregister(&task.judge_uid);
                     let _ = platform_client
                         .report_judge_task(&task.redis_stream_id.clone(), verdict)
                         .await
@@ -167,6 +220,7 @@ impl JudgeWorker {
         problem_slug: String,
         language: Language,
         code: String,
+        cancellation_token: Option<CancellationToken>,
     ) -> Result<JudgeBuilder, JudgeCoreError> {
         if let Some(rclone_client) = self.maybe_rclone_client.as_ref() {
             rclone_client.sync_bucket(&self.package_bucket, &self.package_dir)?;
@@ -187,19 +241,46 @@ impl JudgeWorker {
             anyhow::anyhow!("Failed to write src file")
         })?;
 
-        let builder = JudgeBuilder::new(JudgeBuilderInput {
+        let mut builder = JudgeBuilder::new(JudgeBuilderInput {
             package_type: PackageType::ICPC,
             package_path: problem_package_dir,
             runtime_path: runtime_path.clone(),
             src_language: language,
             src_path: runtime_path.clone().join(&src_file_name),
+            // Left to the `seccomp_profile` of the package, or the language
+            seccomp_profile: None,
+            cancellation_token,
         })?;
+        builder.runtime_config.rlimit_configs.cpu_pinning = self.run_options.cpu_pinning;
+        builder.runtime_config.sandbox_backend = self.run_options.sandbox_backend;
         log::info!("Builder created success: {:?}", builder);
         Ok(builder)
     }
 
-    pub fn run_judge(&self, judge_config: JudgeConfig) -> Result<JudgeResultInfo, anyhow::Error> {
-        judge::common::run_judge(&judge_config)
+    /// `prepare_judge()` on a blocking thread, so that compiling does not block the runtime.
+    pub async fn prepare_judge_async(
+        self: &Arc<Self>,
+        problem_slug: String,
+        language: Language,
+        code: String,
+        cancellation_token: Option<CancellationToken>,
+    ) -> Result<JudgeBuilder, JudgeCoreError> {
+        let worker = Arc::clone(self);
+        tokio::task::spawn_blocking(move || {
+            worker.prepare_judge(problem_slug, language, code, cancellation_token)
+        })
+        .await
+        .map_err(|e| {
+            JudgeCoreError::AnyhowError(anyhow::anyhow!("Blocking prepare task failed: {:?}", e))
+        })?
+    }
+
+    pub async fn run_judge(
+        &self,
+        judge_config: JudgeConfig,
+    ) -> Result<JudgeResultInfo, anyhow::Error> {
+        judge::common::run_judge_async(&judge_config)
+            .await
             .map_err(|e| anyhow::anyhow!("Failed to run judge: {:?}", e))
     }
 }